@@ -0,0 +1,54 @@
+use std::fmt::{Debug, Formatter, Error};
+use bencode::{self, Value};
+use super::{Vm, Host, MAX_THREADS};
+
+/// Why calling a lambda failed.
+pub enum CallError {
+    /// the program itself failed
+    Run(String),
+    /// the program finished, but its output is not a bencode value
+    Return(Vec<u8>, bencode::ParseError)
+}
+
+impl Debug for CallError {
+    fn fmt(&self, f: &mut Formatter)->Result<(), Error> {
+        match self {
+            &CallError::Run(ref err) => write!(f, "{}", err),
+            &CallError::Return(ref output, ref err) => {
+                write!(f, "broken return value {:?}, {:?}", String::from_utf8_lossy(output), err)
+            }
+        }
+    }
+}
+
+impl From<CallError> for String {
+    fn from(e: CallError)->Self {
+        format!("{:?}", e)
+    }
+}
+
+/// Call a lambda the way the runtime does: the program reads its arguments
+/// as a bencode list, and writes its return value as a single bencode value.
+pub fn call(code: &Vm, args: &[Value])->Result<Value, CallError> {
+    call_with(code, args, None)
+}
+
+/// Like `call`, with `host` serving the `%` instruction.
+/// Forking programs run without a host.
+pub fn call_hosted(code: &Vm, args: &[Value], host: &mut Host)->Result<Value, CallError> {
+    call_with(code, args, Some(host))
+}
+
+fn call_with(code: &Vm, args: &[Value], host: Option<&mut Host>)->Result<Value, CallError> {
+    let input = Vec::from(Value::List(args.to_vec()));
+    let output = match host {
+        _ if code.extensions().contains(&"fork") => code.eval_forked(&input, None, MAX_THREADS),
+        Some(host) => code.eval_hosted(&input, None, host),
+        None => code.eval(&input, None)
+    };
+    let output = try!(output.map_err(CallError::Run));
+    match bencode::parse(&mut output.iter().cloned()) {
+        Ok(x) => Ok(x),
+        Err(err) => Err(CallError::Return(output, err))
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Vm, ByteCode};
+use ByteCode::*;
+
+const ORDER: [ByteCode; 8] = [ Gt, Lt, Plus, Minus, Dot, Comma, LeftBracket, RightBracket ];
+
+/// A bf variant spelling every instruction with its own token.
+/// Whitespace in the source is insignificant.
+#[derive(Clone, Debug)]
+pub struct Dialect {
+    pub name: String,
+    // spelling for emitting, the same with whitespace stripped for parsing
+    tokens: Vec<(String, String, ByteCode)>,
+    separator: String
+}
+
+impl Dialect {
+    /// Tokens are given for `><+-.,[]`, in that order.
+    /// Emitted instructions are joined by `separator`.
+    pub fn alias(name: &str, tokens: [&str; 8], separator: &str)->Dialect {
+        Dialect {
+            name: name.to_string(),
+            tokens: tokens.iter().zip(ORDER.iter()).map(|(t, &c)| {
+                (t.to_string(), strip(t), c)
+            }).collect(),
+            separator: separator.to_string()
+        }
+    }
+    pub fn bf()->Dialect {
+        Dialect::alias("bf", [ ">", "<", "+", "-", ".", ",", "[", "]" ], "")
+    }
+    /// See <https://esolangs.org/wiki/Ook!>
+    pub fn ook()->Dialect {
+        Dialect::alias("ook", [
+            "Ook. Ook?", "Ook? Ook.", "Ook. Ook.", "Ook! Ook!",
+            "Ook! Ook.", "Ook. Ook!", "Ook! Ook?", "Ook? Ook!"
+        ], " ")
+    }
+    /// See <https://esolangs.org/wiki/Blub>
+    pub fn blub()->Dialect {
+        Dialect::alias("blub", [
+            "Blub. Blub?", "Blub? Blub.", "Blub. Blub.", "Blub! Blub!",
+            "Blub! Blub.", "Blub. Blub!", "Blub! Blub?", "Blub? Blub!"
+        ], " ")
+    }
+    /// bf extended with procedures, see <https://esolangs.org/wiki/Pbrain>
+    pub fn pbrain()->Dialect {
+        Dialect::find("pbrain").unwrap()
+    }
+    /// One of the named dialects, or bf with extensions joined by `+`, like `pbrain+host`.
+    pub fn find(name: &str)->Option<Dialect> {
+        match name {
+            "ook" => return Some(Dialect::ook()),
+            "blub" => return Some(Dialect::blub()),
+            _ => ()
+        }
+        let mut ret = Dialect::bf();
+        for ext in name.split('+') {
+            let ops: &[ByteCode] = match ext {
+                "bf" => &[],
+                "pbrain" => &[ ProcStart, ProcEnd, ProcCall ],
+                "host" => &[ HostCall ],
+                "fork" => &[ Fork ],
+                _ => return None
+            };
+            for &c in ops {
+                let token = (c as u8 as char).to_string();
+                ret.tokens.push((token.clone(), token, c))
+            }
+        }
+        ret.name = name.to_string();
+        Some(ret)
+    }
+    /// Reads the longest token at each point, the one listed first among equally long ones.
+    pub fn parse(&self, src: &str)->Result<Vm, String> {
+        self.parse_mapped(src).map(|(vm, _)| vm)
+    }
+    /// Like `parse`, also giving the offset in `src` every instruction starts at.
+    pub fn parse_mapped(&self, src: &str)->Result<(Vm, Vec<usize>), String> {
+        // the offset in `src` of every byte left after stripping whitespace
+        let mut offsets = Vec::new();
+        let mut stripped = String::new();
+        for (i, c) in src.char_indices().filter(|&(_, c)| !c.is_whitespace()) {
+            offsets.extend((0 .. c.len_utf8()).map(|x| i + x));
+            stripped.push(c)
+        }
+        let mut pos = 0;
+        let mut ret = Vec::new();
+        let mut map = Vec::new();
+        while pos < stripped.len() {
+            let rest = &stripped[pos ..];
+            let mut longest: Option<(usize, ByteCode)> = None;
+            for &(_, ref token, c) in &self.tokens {
+                if !token.is_empty() && rest.starts_with(&token[..]) && longest.map_or(true, |x| token.len() > x.0) {
+                    longest = Some((token.len(), c))
+                }
+            }
+            match longest {
+                Some((len, c)) => {
+                    ret.push(c);
+                    map.push(offsets[pos]);
+                    pos += len
+                },
+                None => {
+                    let unexpected = rest.chars().take(8).collect::<String>();
+                    return Err(format!("unexpected `{}` in {} source", unexpected, self.name))
+                }
+            }
+        }
+        Ok((Vm(ret), map))
+    }
+    /// Fails on instructions the dialect has no token for.
+    pub fn emit(&self, vm: &Vm)->Result<String, String> {
+        let mut ret = Vec::new();
+        for &c in &vm.0 {
+            match self.tokens.iter().find(|&&(_, _, x)| x == c) {
+                Some(&(ref token, _, _)) => ret.push(&token[..]),
+                None => return Err(format!("`{}` cannot be written in {}", c as u8 as char, self.name))
+            }
+        }
+        Ok(ret.join(&self.separator))
+    }
+}
+
+fn strip(s: &str)->String {
+    s.chars().filter(|c| !c.is_whitespace()).collect()
+}
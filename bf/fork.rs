@@ -0,0 +1,87 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::spawn;
+use super::{Vm, ByteCode, State, exec, CANCELLED};
+
+/// Thread limit `call` runs forking programs with.
+pub const MAX_THREADS: usize = 16;
+
+struct Shared {
+    code: Vec<ByteCode>,
+    input: Vec<u8>,
+    limit: Option<usize>,
+    max_threads: usize,
+    threads: AtomicUsize,
+    /// set once a thread failed, stopping all the others
+    failed: AtomicBool
+}
+
+impl Vm {
+    /// Run a program that may fork with `Y`, each thread on its own OS thread,
+    /// with its own copy of the tape and of the input not read yet.
+    ///
+    /// The output is deterministic: what a thread wrote itself, followed by
+    /// the output of every thread it forked, in the order they were forked.
+    /// Fails once more than `max_threads` threads were started in total,
+    /// and `limit` bounds the instructions executed by each of them.
+    /// A failing thread stops all the others, and every thread is joined before returning.
+    pub fn eval_forked(&self, input: &[u8], limit: Option<usize>, max_threads: usize)->Result<Vec<u8>, String> {
+        let shared = Arc::new(Shared {
+            code: self.0.clone(),
+            input: input.to_vec(),
+            limit: limit,
+            max_threads: max_threads,
+            threads: AtomicUsize::new(1),
+            failed: AtomicBool::new(false)
+        });
+        run(shared, State::new(), 0)
+    }
+}
+
+fn run(shared: Arc<Shared>, mut state: State, pos: usize)->Result<Vec<u8>, String> {
+    let pos = Cell::new(pos);
+    let mut output = Vec::new();
+    let mut children = Vec::new();
+    let ret = {
+        let mut read = || {
+            let now = pos.get();
+            pos.set(now + 1);
+            shared.input.get(now).cloned()
+        };
+        let mut fork = |child: State| {
+            if shared.threads.fetch_add(1, Ordering::SeqCst) >= shared.max_threads {
+                return Err("thread limit exceeded".to_string())
+            }
+            let (shared, pos) = (shared.clone(), pos.get());
+            children.push(spawn(move || run(shared, child, pos)));
+            Ok(())
+        };
+        exec(&shared.code, &mut state, &mut read, &mut |b| output.push(b),
+             shared.limit, None, Some(&mut fork), Some(&shared.failed))
+    };
+    if ret.is_err() {
+        shared.failed.store(true, Ordering::SeqCst)
+    }
+    let mut ret = ret.map(|_| output);
+    for child in children {
+        let rslt = match child.join() {
+            Ok(x) => x,
+            Err(_) => Err("forked thread panicked".to_string())
+        };
+        ret = match (ret, rslt) {
+            (Ok(mut output), Ok(x)) => {
+                output.extend(x);
+                Ok(output)
+            },
+            (Ok(_), Err(err)) => {
+                shared.failed.store(true, Ordering::SeqCst);
+                Err(err)
+            },
+            // a thread stopped by the failure of another tells less than that failure
+            (Err(first), Err(err)) if first == CANCELLED && err != CANCELLED => Err(err),
+            (ret, _) => ret
+        }
+    }
+    ret
+}
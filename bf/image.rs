@@ -0,0 +1,159 @@
+//! On-disk format for compiled programs:
+//!
+//! * the magic bytes `BFVM` and a version byte,
+//! * a flag byte, bit 0 telling whether a source map follows the code,
+//! * the number of runs, then each run as its opcode and repeat count,
+//! * optionally, the source offset of every instruction, delta encoded.
+//!
+//! All numbers are LEB128 varints.
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use super::{Vm, ByteCode, Dialect};
+
+pub const MAGIC: &'static [u8] = b"BFVM";
+pub const VERSION: u8 = 1;
+/// Most instructions `decode` accepts, bounding what a corrupt image can allocate.
+pub const MAX_LEN: usize = 1 << 24;
+
+const FLAG_SOURCE_MAP: u8 = 1;
+
+fn put_varint(ret: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        ret.push((n & 0x7F) as u8 | 0x80);
+        n >>= 7
+    }
+    ret.push(n as u8)
+}
+
+fn get_varint(s: &mut Iterator<Item=u8>)->Result<usize, String> {
+    let mut ret = 0;
+    let mut shift = 0;
+    loop {
+        let b = match s.next() {
+            Some(b) => b,
+            None => return Err("unexpected end of image".to_string())
+        };
+        if shift >= 64 {
+            return Err("varint overflow".to_string())
+        }
+        ret |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            return Ok(ret)
+        }
+        shift += 7
+    }
+}
+
+impl Vm {
+    /// Serialize, optionally with the source offset of every instruction,
+    /// which must be one offset per instruction, in increasing order.
+    pub fn encode(&self, source_map: Option<&[usize]>)->Result<Vec<u8>, String> {
+        let mut runs: Vec<(ByteCode, usize)> = Vec::new();
+        for &c in &self.0 {
+            match runs.last_mut() {
+                Some(&mut (last, ref mut cnt)) if last == c => *cnt += 1,
+                _ => runs.push((c, 1))
+            }
+        }
+        let mut ret = MAGIC.to_vec();
+        ret.push(VERSION);
+        ret.push(if source_map.is_some() { FLAG_SOURCE_MAP } else { 0 });
+        put_varint(&mut ret, runs.len());
+        for (c, cnt) in runs {
+            ret.push(c as u8);
+            put_varint(&mut ret, cnt)
+        }
+        if let Some(map) = source_map {
+            if map.len() != self.0.len() {
+                return Err(format!("source map has {} offsets for {} instructions", map.len(), self.0.len()))
+            }
+            let mut last = 0;
+            for &offset in map {
+                if offset < last {
+                    return Err(format!("source map goes back from offset {} to {}", last, offset))
+                }
+                put_varint(&mut ret, offset - last);
+                last = offset
+            }
+        }
+        Ok(ret)
+    }
+    /// Deserialize a program, and its source map if one was saved.
+    pub fn decode(bytes: &[u8])->Result<(Vm, Option<Vec<usize>>), String> {
+        if bytes.len() < MAGIC.len() + 2 || &bytes[.. MAGIC.len()] != MAGIC {
+            return Err("not a bf image".to_string())
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(format!("unsupported image version {}", bytes[MAGIC.len()]))
+        }
+        let flags = bytes[MAGIC.len() + 1];
+        let mut s = bytes[MAGIC.len() + 2 ..].iter().cloned();
+        let mut code = Vec::new();
+        let runs = try!(get_varint(&mut s));
+        // every run takes at least an opcode and a count byte
+        if runs > s.len() / 2 {
+            return Err("unexpected end of image".to_string())
+        }
+        for _ in 0 .. runs {
+            let c = match s.next().map(ByteCode::from_u8) {
+                Some(Some(c)) => c,
+                Some(None) => return Err("unknown opcode in image".to_string()),
+                None => return Err("unexpected end of image".to_string())
+            };
+            let cnt = try!(get_varint(&mut s));
+            if cnt > MAX_LEN - code.len() {
+                return Err(format!("image longer than {} instructions", MAX_LEN))
+            }
+            code.extend((0 .. cnt).map(|_| c))
+        }
+        let vm = Vm(code);
+        let map = if flags & FLAG_SOURCE_MAP != 0 {
+            let mut map = Vec::new();
+            let mut last: usize = 0;
+            for _ in 0 .. vm.0.len() {
+                last = match last.checked_add(try!(get_varint(&mut s))) {
+                    Some(x) => x,
+                    None => return Err("source map offset overflow".to_string())
+                };
+                map.push(last)
+            }
+            Some(map)
+        } else {
+            None
+        };
+        if s.next().is_some() {
+            return Err("trailing bytes after image".to_string())
+        }
+        Ok((vm, map))
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P)->Result<(), String> {
+        self.save_with_map(path, None)
+    }
+    pub fn save_with_map<P: AsRef<Path>>(&self, path: P, source_map: Option<&[usize]>)->Result<(), String> {
+        let bytes = try!(self.encode(source_map));
+        let mut file = try!(File::create(path).map_err(|e| e.to_string()));
+        file.write_all(&bytes).map_err(|e| e.to_string())
+    }
+    pub fn load<P: AsRef<Path>>(path: P)->Result<Vm, String> {
+        Vm::load_with_map(path).map(|(vm, _)| vm)
+    }
+    pub fn load_with_map<P: AsRef<Path>>(path: P)->Result<(Vm, Option<Vec<usize>>), String> {
+        let mut bytes = Vec::new();
+        let mut file = try!(File::open(path).map_err(|e| e.to_string()));
+        try!(file.read_to_end(&mut bytes).map_err(|e| e.to_string()));
+        Vm::decode(&bytes)
+    }
+}
+
+/// The offset in `source` of every instruction `Convert` reads from it,
+/// the `#name` header skipped. Whitespace is allowed between instructions.
+pub fn source_map(source: &str)->Result<Vec<usize>, String> {
+    let (dialect, start) = match super::header(source) {
+        Some(Ok(x)) => x,
+        Some(Err(err)) => return Err(err),
+        None => (Dialect::bf(), 0)
+    };
+    let (_, map) = try!(dialect.parse_mapped(&source[start ..]));
+    Ok(map.into_iter().map(|x| x + start).collect())
+}
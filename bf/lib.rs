@@ -1,11 +1,31 @@
+extern crate bencode;
+
 use std::sync::mpsc::{Sender, Receiver};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::fmt::{Formatter, Error, Display};
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+mod call;
+pub mod dialect;
+mod fork;
+pub mod image;
+pub mod search;
+pub mod testing;
+mod opt;
+mod pretty;
+
+pub use call::{call, call_hosted, CallError};
+pub use dialect::Dialect;
+pub use fork::MAX_THREADS;
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Vm(Vec<ByteCode>);
 
 impl Display for Vm {
     fn fmt(&self, f: &mut Formatter)->Result<(), Error> {
+        let exts = self.extensions();
+        if !exts.is_empty() {
+            try!(write!(f, "#{} ", exts.join("+")))
+        }
         for &c in &self.0 {
             try!(write!(f, "{}", c as u8 as char))
         }
@@ -13,14 +33,15 @@ impl Display for Vm {
     }
 }
 
+/// The source text, dialect header included, as `Convert` reads it back.
 impl From<Vm> for Vec<u8> {
     fn from(v: Vm)->Self {
-        v.0.into_iter().map(|x| x as u8).collect()
+        v.to_string().into_bytes()
     }
 }
 
 #[repr(u8)]
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub enum ByteCode {
     Lt = b'<',
     Gt = b'>',
@@ -29,7 +50,44 @@ pub enum ByteCode {
     Dot = b'.',
     Comma = b',',
     LeftBracket = b'[',
-    RightBracket = b']'
+    RightBracket = b']',
+    /// pbrain only, defines the procedure numbered by the current cell
+    ProcStart = b'(',
+    /// pbrain only, ends a procedure definition
+    ProcEnd = b')',
+    /// pbrain only, calls the procedure numbered by the current cell
+    ProcCall = b':',
+    /// hands the tape from the current cell on over to the `Host`
+    HostCall = b'%',
+    /// brainfork only, splits into two threads, see `Vm::eval_forked`
+    Fork = b'Y'
+}
+
+/// Number of cells, starting from the current one, a `Host` gets to see.
+pub const HOST_WINDOW: usize = 16;
+
+/// Runtime services a program reaches with the `%` instruction.
+pub trait Host {
+    /// The host may read and overwrite `window` in place.
+    fn call(&mut self, window: &mut [u8])->Result<(), String>;
+}
+
+impl ByteCode {
+    /// The dialect extension an instruction belongs to, if not plain bf.
+    pub fn extension(self)->Option<&'static str> {
+        match self {
+            ByteCode::ProcStart | ByteCode::ProcEnd | ByteCode::ProcCall => Some("pbrain"),
+            ByteCode::HostCall => Some("host"),
+            ByteCode::Fork => Some("fork"),
+            _ => None
+        }
+    }
+    fn from_u8(b: u8)->Option<ByteCode> {
+        use ByteCode::*;
+        [ Lt, Gt, Plus, Minus, Dot, Comma, LeftBracket, RightBracket,
+          ProcStart, ProcEnd, ProcCall, HostCall, Fork ]
+            .iter().cloned().find(|&c| c as u8 == b)
+    }
 }
 
 pub enum Convert {
@@ -52,10 +110,32 @@ impl From<String> for Convert {
     }
 }
 
+/// The dialect a leading `#name` selects, and where the code after that header starts.
+fn header(s: &str)->Option<Result<(Dialect, usize), String>> {
+    if !s.starts_with('#') {
+        return None
+    }
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    Some(match Dialect::find(&s[1 .. end]) {
+        Some(dialect) => Ok((dialect, end)),
+        None => Err(format!("unknown dialect `{}`", &s[1 .. end]))
+    })
+}
+
 impl<'a> From<&'a str> for Convert {
     fn from(s: &str)->Self {
+            // a leading `#name` selects the dialect the rest is written in
+            match header(s) {
+                Some(Ok((dialect, end))) => return match dialect.parse(&s[end ..]) {
+                    Ok(vm) => Convert::Ok(vm),
+                    Err(err) => Convert::Err(err)
+                },
+                Some(Err(err)) => return Convert::Err(err),
+                None => ()
+            }
+            // whitespace is insignificant, as in the dialects
             let mut vec = Vec::new();
-            for c in s.chars() {
+            for c in s.chars().filter(|c| !c.is_whitespace()) {
                 vec.push(match c {
                     '<' => ByteCode::Lt,
                     '>' => ByteCode::Gt,
@@ -74,6 +154,19 @@ impl<'a> From<&'a str> for Convert {
 
 
 impl Vm {
+    /// Names of the dialect extensions used, sorted.
+    pub fn extensions(&self)->Vec<&'static str> {
+        let mut ret = Vec::new();
+        for &c in &self.0 {
+            if let Some(ext) = c.extension() {
+                if !ret.contains(&ext) {
+                    ret.push(ext)
+                }
+            }
+        }
+        ret.sort();
+        ret
+    }
     pub fn add_one()->Vm {
         Vm(vec![
             ByteCode::Comma,
@@ -115,62 +208,172 @@ impl Vm {
         Vm(ret)
     }
     pub fn run(&self, snd: Sender<u8>, rcv: Receiver<u8>)->Result<(), String> {
-        let mut mem: Vec<u8> = vec![ 0 ];
-        let ref vm = self.0;
-        let mut pc: usize = 0;
-        let mut ptr: usize = 0;
-        loop {
-            match vm[pc] {
-                ByteCode::Gt => {
-                    ptr += 1;
-                    if mem.len() <= ptr {
-                        mem.push(0)
-                    }
-                },
-                ByteCode::Lt => {
-                    if ptr == 0 {
-                        return Err("illegal pointer movement".to_string())
-                    }
-                    ptr -= 1
-                },
-                ByteCode::Plus => {
-                    mem[ptr] = ((mem[ptr] as u32 + 1) % 256) as u8;
-                },
-                ByteCode::Minus => {
-                    mem[ptr] = ((mem[ptr] as i32 - 1) % 256) as u8;
-                },
-                ByteCode::Dot => {
-                    snd.send(mem[ptr]).unwrap()
+        let mut state = State::new();
+        exec(&self.0, &mut state, &mut || rcv.recv().ok(), &mut |b| snd.send(b).unwrap(), None, None, None, None)
+    }
+    pub fn run_hosted(&self, snd: Sender<u8>, rcv: Receiver<u8>, host: &mut Host)->Result<(), String> {
+        let mut state = State::new();
+        exec(&self.0, &mut state, &mut || rcv.recv().ok(), &mut |b| snd.send(b).unwrap(),
+             None, Some(host), None, None)
+    }
+    /// Run with a fixed input, collecting the output.
+    /// Fails once more than `limit` instructions were executed.
+    pub fn eval(&self, input: &[u8], limit: Option<usize>)->Result<Vec<u8>, String> {
+        eval(&self.0, input, limit, None)
+    }
+    pub fn eval_hosted(&self, input: &[u8], limit: Option<usize>, host: &mut Host)->Result<Vec<u8>, String> {
+        eval(&self.0, input, limit, Some(host))
+    }
+}
+
+fn eval(code: &[ByteCode], input: &[u8], limit: Option<usize>, host: Option<&mut Host>)->Result<Vec<u8>, String> {
+    let mut input = input.iter().cloned();
+    let mut output = Vec::new();
+    let mut state = State::new();
+    try!(exec(code, &mut state, &mut || input.next(), &mut |b| output.push(b), limit, host, None, None));
+    Ok(output)
+}
+
+fn jumps(code: &[ByteCode])->Result<Vec<usize>, String> {
+    let mut ret = vec![ 0; code.len() ];
+    let mut stack = Vec::new();
+    for (pc, &c) in code.iter().enumerate() {
+        match c {
+            ByteCode::LeftBracket | ByteCode::ProcStart => stack.push(pc),
+            ByteCode::RightBracket | ByteCode::ProcEnd => match stack.pop() {
+                Some(left) if (code[left] == ByteCode::LeftBracket) ==
+                              (c == ByteCode::RightBracket) => {
+                    ret[left] = pc;
+                    ret[pc] = left
                 },
-                ByteCode::Comma => {
-                    mem[ptr] = rcv.recv().unwrap()
+                _ => return Err(format!("unbalanced `{}`", c as u8 as char))
+            },
+            _ => ()
+        }
+    }
+    match stack.pop() {
+        None => Ok(ret),
+        Some(left) => Err(format!("unbalanced `{}`", code[left] as u8 as char))
+    }
+}
+
+const MAX_CALL_DEPTH: usize = 4096;
+/// What `exec` fails with once its cancel flag is set.
+const CANCELLED: &'static str = "cancelled";
+
+/// Everything a running program owns, so that a `Y` can copy it.
+#[derive(Clone)]
+struct State {
+    pc: usize,
+    ptr: usize,
+    mem: Vec<u8>,
+    steps: usize,
+    procs: Vec<Option<usize>>,
+    calls: Vec<usize>
+}
+
+impl State {
+    fn new()->State {
+        State { pc: 0, ptr: 0, mem: vec![ 0 ], steps: 0, procs: vec![ None; 256 ], calls: Vec::new() }
+    }
+}
+
+fn exec<R, W>(code: &[ByteCode], state: &mut State, read: &mut R, write: &mut W,
+              limit: Option<usize>, mut host: Option<&mut Host>,
+              mut fork: Option<&mut FnMut(State)->Result<(), String>>,
+              cancel: Option<&AtomicBool>)->Result<(), String>
+    where R: FnMut()->Option<u8>, W: FnMut(u8) {
+    let jumps = try!(jumps(code));
+    let State { ref mut pc, ref mut ptr, ref mut mem, ref mut steps, ref mut procs, ref mut calls } = *state;
+    while *pc < code.len() {
+        if let Some(limit) = limit {
+            if *steps == limit {
+                return Err("step limit exceeded".to_string())
+            }
+            *steps += 1
+        }
+        if let Some(cancel) = cancel {
+            if cancel.load(Ordering::SeqCst) {
+                return Err(CANCELLED.to_string())
+            }
+        }
+        match code[*pc] {
+            ByteCode::Gt => {
+                *ptr += 1;
+                if mem.len() <= *ptr {
+                    mem.push(0)
+                }
+            },
+            ByteCode::Lt => {
+                if *ptr == 0 {
+                    return Err("illegal pointer movement".to_string())
+                }
+                *ptr -= 1
+            },
+            ByteCode::Plus => mem[*ptr] = mem[*ptr].wrapping_add(1),
+            ByteCode::Minus => mem[*ptr] = mem[*ptr].wrapping_sub(1),
+            ByteCode::Dot => write(mem[*ptr]),
+            ByteCode::Comma => match read() {
+                Some(b) => mem[*ptr] = b,
+                None => return Err("input exhausted".to_string())
+            },
+            ByteCode::LeftBracket => if mem[*ptr] == 0 {
+                *pc = jumps[*pc]
+            },
+            ByteCode::RightBracket => if mem[*ptr] != 0 {
+                *pc = jumps[*pc]
+            },
+            ByteCode::ProcStart => {
+                procs[mem[*ptr] as usize] = Some(*pc);
+                *pc = jumps[*pc]
+            },
+            ByteCode::ProcEnd => match calls.pop() {
+                Some(ret) => *pc = ret,
+                None => return Err("procedure end reached outside of a call".to_string())
+            },
+            ByteCode::ProcCall => match procs[mem[*ptr] as usize] {
+                Some(start) if calls.len() < MAX_CALL_DEPTH => {
+                    calls.push(*pc);
+                    *pc = start
                 },
-                ByteCode::LeftBracket => {
-                    if mem[ptr] == 0 {
-                        while vm[pc] != ByteCode::RightBracket {
-                            pc += 1;
-                            if pc == vm.len() {
-                                return Err("pc out of range".to_string())
-                            }
-                        }
+                Some(_) => return Err("call stack overflow".to_string()),
+                None => return Err(format!("procedure {} not defined", mem[*ptr]))
+            },
+            ByteCode::HostCall => match host {
+                Some(ref mut host) => {
+                    while mem.len() < *ptr + HOST_WINDOW {
+                        mem.push(0)
                     }
+                    try!(host.call(&mut mem[*ptr .. *ptr + HOST_WINDOW]))
                 },
-                ByteCode::RightBracket => {
-                    if mem[ptr] != 0 {
-                        while vm[pc] != ByteCode::LeftBracket {
-                            if pc == 0 {
-                                return Err("pc out of range".to_string())
-                            }
-                            pc -= 1
-                        }
+                None => return Err("no host to call".to_string())
+            },
+            // the child continues one cell to the right, set to 1,
+            // while the parent keeps going with the current cell cleared
+            ByteCode::Fork => match fork {
+                Some(ref mut fork) => {
+                    let mut child = State {
+                        pc: *pc + 1,
+                        ptr: *ptr + 1,
+                        mem: mem.clone(),
+                        steps: *steps,
+                        procs: procs.clone(),
+                        calls: calls.clone()
+                    };
+                    if child.mem.len() <= child.ptr {
+                        child.mem.push(0)
                     }
+                    child.mem[child.ptr] = 1;
+                    try!(fork(child));
+                    mem[*ptr] = 0
                 },
-            }
-            pc += 1;
-            if pc == vm.len() {
-                break
-            }
+                None => return Err("forking is not supported here".to_string())
+            },
         }
-        Ok(())
+        *pc += 1
     }
+    Ok(())
 }
+
+#[cfg(test)]
+mod tests;
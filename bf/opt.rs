@@ -0,0 +1,74 @@
+use super::{Vm, ByteCode, jumps};
+use ByteCode::*;
+
+impl Vm {
+    /// Peephole simplification into a canonical form:
+    /// cancels adjacent `+-`, `-+`, `<>` and `><`,
+    /// folds runs of `+` and `-` modulo 256,
+    /// drops loops that can never be entered,
+    /// and spells every cell-clearing loop as `[-]`.
+    ///
+    /// Cancelling `<>` also removes the error a leading `<` would raise.
+    pub fn simplify(&self)->Vm {
+        let mut code = self.0.clone();
+        loop {
+            let next = fold(&cancel(&code));
+            if next == code {
+                return Vm(code)
+            }
+            code = next
+        }
+    }
+}
+
+fn cancel(code: &[ByteCode])->Vec<ByteCode> {
+    let jumps = jumps(code).ok();
+    let mut ret: Vec<ByteCode> = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        match (ret.last().cloned(), code[pc]) {
+            (Some(Plus), Minus) | (Some(Minus), Plus) |
+            (Some(Gt), Lt) | (Some(Lt), Gt) => {
+                ret.pop();
+            },
+            // the current cell is known to be zero here
+            (None, LeftBracket) | (Some(RightBracket), LeftBracket) if jumps.is_some() => {
+                pc = jumps.as_ref().unwrap()[pc]
+            },
+            (_, c) => ret.push(c)
+        }
+        pc += 1
+    }
+    ret
+}
+
+fn fold(code: &[ByteCode])->Vec<ByteCode> {
+    let mut ret = Vec::new();
+    let mut run: i32 = 0;
+    for &c in code.iter().chain(Some(&Dot)) {
+        match c {
+            Plus => run += 1,
+            Minus => run -= 1,
+            c => {
+                let net = (run % 256 + 256) % 256;
+                if net <= 128 {
+                    ret.extend((0 .. net).map(|_| Plus))
+                } else {
+                    ret.extend((net .. 256).map(|_| Minus))
+                }
+                ret.push(c);
+                run = 0
+            }
+        }
+    }
+    // drop the sentinel flushing the last run
+    ret.pop();
+    let mut pc = 0;
+    while pc + 2 < ret.len() {
+        if ret[pc] == LeftBracket && ret[pc + 1] == Plus && ret[pc + 2] == RightBracket {
+            ret[pc + 1] = Minus
+        }
+        pc += 1
+    }
+    ret
+}
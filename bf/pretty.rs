@@ -0,0 +1,60 @@
+use super::{Vm, ByteCode};
+
+const INDENT: &'static str = "    ";
+
+impl Vm {
+    /// Render one line per straight-line run, with loop bodies indented
+    /// one level deeper than their brackets, and likewise for procedures.
+    /// Runs longer than `width` columns, indentation included, are wrapped.
+    /// Programs using extensions start with their `#...` header line, as `Display` writes it,
+    /// and `Convert` reads the result back.
+    pub fn pretty(&self, width: Option<usize>)->String {
+        let mut lines: Vec<(usize, String)> = Vec::new();
+        let mut depth = 0;
+        let mut run = String::new();
+        for &c in &self.0 {
+            match c {
+                ByteCode::LeftBracket | ByteCode::RightBracket |
+                ByteCode::ProcStart | ByteCode::ProcEnd => {
+                    if !run.is_empty() {
+                        lines.push((depth, run.clone()));
+                        run.clear()
+                    }
+                    if (c == ByteCode::RightBracket || c == ByteCode::ProcEnd) && depth > 0 {
+                        depth -= 1
+                    }
+                    lines.push((depth, (c as u8 as char).to_string()));
+                    if c == ByteCode::LeftBracket || c == ByteCode::ProcStart {
+                        depth += 1
+                    }
+                },
+                c => run.push(c as u8 as char)
+            }
+        }
+        if !run.is_empty() {
+            lines.push((depth, run))
+        }
+        let mut ret = String::new();
+        let exts = self.extensions();
+        if !exts.is_empty() {
+            ret.push_str(&format!("#{}\n", exts.join("+")))
+        }
+        for (depth, line) in lines {
+            let indent = INDENT.len() * depth;
+            let limit = match width {
+                Some(width) if width > indent => width - indent,
+                Some(_) => 1,
+                None => line.len()
+            };
+            let chars = line.chars().collect::<Vec<_>>();
+            for chunk in chars.chunks(limit) {
+                for _ in 0 .. depth {
+                    ret.push_str(INDENT)
+                }
+                ret.extend(chunk.iter().cloned());
+                ret.push('\n')
+            }
+        }
+        ret
+    }
+}
@@ -0,0 +1,95 @@
+use super::{Vm, ByteCode};
+
+const OPS: [ByteCode; 8] = [
+    ByteCode::Comma,
+    ByteCode::Dot,
+    ByteCode::Plus,
+    ByteCode::Minus,
+    ByteCode::Gt,
+    ByteCode::Lt,
+    ByteCode::LeftBracket,
+    ByteCode::RightBracket
+];
+
+/// An input fed to the program, and the output it is expected to produce.
+pub type Example = (Vec<u8>, Vec<u8>);
+
+/// Enumerate programs by increasing length, up to `max_len` instructions,
+/// and return the first one producing the expected output for every example.
+/// Each run is cut off after `max_steps` instructions, and the search gives up
+/// with `None` after trying `max_nodes` partial programs.
+pub fn shortest(examples: &[Example], max_len: usize, max_steps: usize, max_nodes: usize)->Option<Vm> {
+    let mut search = Search { examples: examples, max_steps: max_steps, nodes: max_nodes, code: Vec::new() };
+    for len in 0 .. max_len + 1 {
+        if search.dfs(0, len) {
+            return Some(Vm(search.code))
+        }
+        if search.nodes == 0 {
+            break
+        }
+    }
+    None
+}
+
+struct Search<'a> {
+    examples: &'a [Example],
+    max_steps: usize,
+    // partial programs left to try
+    nodes: usize,
+    code: Vec<ByteCode>
+}
+
+impl<'a> Search<'a> {
+    // Output is only ever appended, so once every loop is closed the output of
+    // the prefix must already be a prefix of the expected output.
+    fn check(&self, complete: bool)->bool {
+        let vm = Vm(self.code.clone());
+        self.examples.iter().all(|&(ref input, ref output)| {
+            match vm.eval(input, Some(self.max_steps)) {
+                Ok(ref x) if complete => x == output,
+                Ok(ref x) => output.starts_with(x),
+                Err(_) => false
+            }
+        })
+    }
+    fn pruned(&self, op: ByteCode, depth: usize, len: usize)->bool {
+        use ByteCode::*;
+        let last = self.code.last().cloned();
+        let rest = len - self.code.len() - 1;
+        match (last, op) {
+            (Some(Plus), Minus) | (Some(Minus), Plus) => true,
+            (Some(Gt), Lt) | (Some(Lt), Gt) => true,
+            // the cell is zero at the very start and right after a loop
+            (None, LeftBracket) | (Some(RightBracket), LeftBracket) => true,
+            (Some(LeftBracket), RightBracket) => true,
+            (_, RightBracket) => depth == 0 || rest < depth - 1,
+            (_, LeftBracket) => rest < depth + 2,
+            _ => rest < depth
+        }
+    }
+    fn dfs(&mut self, depth: usize, len: usize)->bool {
+        if self.code.len() == len {
+            return depth == 0 && self.check(true)
+        }
+        for &op in &OPS {
+            if self.pruned(op, depth, len) {
+                continue
+            }
+            if self.nodes == 0 {
+                return false
+            }
+            self.nodes -= 1;
+            let depth = match op {
+                ByteCode::LeftBracket => depth + 1,
+                ByteCode::RightBracket => depth - 1,
+                _ => depth
+            };
+            self.code.push(op);
+            if (depth != 0 || self.check(false)) && self.dfs(depth, len) {
+                return true
+            }
+            self.code.pop();
+        }
+        false
+    }
+}
@@ -0,0 +1,188 @@
+//! Declarative tests for bf programs, read from `.bft` files like
+//!
+//! ```text
+//! # a program under test is given inline, or by a macro name the embedder resolves
+//! program ,[.,]
+//! case "abc" -> "abc"
+//! case "" -> error "input exhausted"
+//!
+//! macro add_one
+//! case args "a" -> "l1:be"
+//! case args "a" -> "l1:be" steps 100
+//! ```
+//!
+//! Inputs are either raw bytes or `args`, a bencode list of byte strings.
+//! Strings take the escapes `\n`, `\r`, `\t`, `\\`, `\"` and `\xNN`.
+use super::{Vm, Convert, Host, MAX_THREADS};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Subject {
+    Program(Vm),
+    Macro(String)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expect {
+    Output(Vec<u8>),
+    /// the run fails with a message containing this
+    Error(String)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    pub line: usize,
+    pub input: Vec<u8>,
+    pub expect: Expect,
+    pub max_steps: Option<usize>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suite {
+    pub subject: Subject,
+    pub cases: Vec<Case>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail(Result<Vec<u8>, String>)
+}
+
+impl Case {
+    pub fn run(&self, vm: &Vm)->Outcome {
+        self.run_with(vm, None)
+    }
+    /// Like `run`, with `host` serving the `%` instruction.
+    /// Forking programs run without a host, as in `call_hosted`.
+    pub fn run_hosted(&self, vm: &Vm, host: &mut Host)->Outcome {
+        self.run_with(vm, Some(host))
+    }
+    fn run_with(&self, vm: &Vm, host: Option<&mut Host>)->Outcome {
+        let rslt = match host {
+            _ if vm.extensions().contains(&"fork") => vm.eval_forked(&self.input, self.max_steps, MAX_THREADS),
+            Some(host) => vm.eval_hosted(&self.input, self.max_steps, host),
+            None => vm.eval(&self.input, self.max_steps)
+        };
+        let pass = match (&self.expect, &rslt) {
+            (&Expect::Output(ref x), &Ok(ref y)) => x == y,
+            (&Expect::Error(ref x), &Err(ref y)) => y.contains(&x[..]),
+            _ => false
+        };
+        if pass {
+            Outcome::Pass
+        } else {
+            Outcome::Fail(rslt)
+        }
+    }
+}
+
+pub fn parse(src: &str)->Result<Vec<Suite>, String> {
+    let mut ret: Vec<Suite> = Vec::new();
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        let (word, rest) = match line.find(' ') {
+            Some(x) => (&line[.. x], line[x ..].trim()),
+            None => (line, "")
+        };
+        let err = |msg: String| format!("line {}: {}", line_no, msg);
+        match word {
+            "program" => {
+                let vm = try!(<Result<_, _>>::from(Convert::from(rest)).map_err(&err));
+                ret.push(Suite { subject: Subject::Program(vm), cases: Vec::new() })
+            },
+            "macro" => ret.push(Suite { subject: Subject::Macro(rest.to_string()), cases: Vec::new() }),
+            "case" => match ret.last_mut() {
+                Some(suite) => suite.cases.push(try!(parse_case(line_no, rest).map_err(&err))),
+                None => return Err(err("case before any program or macro".to_string()))
+            },
+            x => return Err(err(format!("unknown directive `{}`", x)))
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_case(line: usize, s: &str)->Result<Case, String> {
+    let mut s = s;
+    let input = if s.starts_with("args") {
+        s = s["args".len() ..].trim_start();
+        let mut input = vec![ b'l' ];
+        while s.starts_with('"') {
+            let (arg, rest) = try!(parse_str(s));
+            input.extend(format!("{}:", arg.len()).bytes());
+            input.extend(arg);
+            s = rest.trim_start()
+        }
+        input.push(b'e');
+        input
+    } else {
+        let (input, rest) = try!(parse_str(s));
+        s = rest.trim_start();
+        input
+    };
+    if !s.starts_with("->") {
+        return Err("`->` expected".to_string())
+    }
+    s = s["->".len() ..].trim_start();
+    let expect = if s.starts_with("error") {
+        let (msg, rest) = try!(parse_str(s["error".len() ..].trim_start()));
+        s = rest.trim_start();
+        Expect::Error(String::from_utf8_lossy(&msg).into_owned())
+    } else {
+        let (output, rest) = try!(parse_str(s));
+        s = rest.trim_start();
+        Expect::Output(output)
+    };
+    let max_steps = if s.starts_with("steps") {
+        match s["steps".len() ..].trim().parse() {
+            Ok(x) => Some(x),
+            Err(_) => return Err("number of steps expected".to_string())
+        }
+    } else if s.is_empty() {
+        None
+    } else {
+        return Err(format!("unexpected `{}`", s))
+    };
+    Ok(Case { line: line, input: input, expect: expect, max_steps: max_steps })
+}
+
+fn parse_str(s: &str)->Result<(Vec<u8>, &str), String> {
+    if !s.starts_with('"') {
+        return Err("string expected".to_string())
+    }
+    let mut ret = Vec::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((idx, c)) = chars.next() {
+        let c = match c {
+            '"' => return Ok((ret, &s[idx + 1 ..])),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 'r')) => '\r',
+                Some((_, 't')) => '\t',
+                Some((_, '\\')) => '\\',
+                Some((_, '"')) => '"',
+                // `from_str_radix` alone would take a sign, like in `\x+F`
+                Some((idx, 'x')) => match s.get(idx + 1 .. idx + 3)
+                                           .filter(|x| x.chars().all(|c| c.is_digit(16)))
+                                           .map(|x| u8::from_str_radix(x, 16)) {
+                    Some(Ok(b)) => {
+                        chars.next();
+                        chars.next();
+                        ret.push(b);
+                        continue
+                    },
+                    _ => return Err("two hex digits expected after `\\x`".to_string())
+                },
+                Some((_, c)) => return Err(format!("unknown escape `\\{}`", c)),
+                None => break
+            },
+            c => c
+        };
+        let mut buf = [ 0; 4 ];
+        ret.extend(c.encode_utf8(&mut buf).bytes())
+    }
+    Err("unterminated string".to_string())
+}
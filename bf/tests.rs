@@ -0,0 +1,200 @@
+use super::*;
+
+fn compile(s: &str)->Vm {
+    <Result<_, _>>::from(Convert::from(s)).unwrap()
+}
+
+#[test]
+fn test_nested_loops() {
+    let vm = compile("++[>++[>+++<-]<-]>>.");
+    assert_eq!(vm.eval(b"", None).unwrap(), vec![ 12 ])
+}
+
+#[test]
+fn test_step_limit() {
+    assert!(compile("+[]").eval(b"", Some(100)).is_err())
+}
+
+#[test]
+fn test_shortest_constant() {
+    let examples = vec![ (Vec::new(), vec![ 3, 3 ]) ];
+    assert_eq!(search::shortest(&examples, 6, 100, 100000).unwrap().to_string(), "+++..")
+}
+
+#[test]
+fn test_shortest_add_one() {
+    let examples = vec![
+        (b"l1:ae".to_vec(), b"1:b".to_vec()),
+        (b"l1:xe".to_vec(), b"1:y".to_vec())
+    ];
+    assert_eq!(search::shortest(&examples, 8, 100, 100000).unwrap().to_string(), ",,.,.,+.");
+    assert!(search::shortest(&examples, 8, 100, 1000).is_none())
+}
+
+#[test]
+fn test_simplify() {
+    assert_eq!(compile("[->+<]+-+><<>-+[-]").simplify().to_string(), "+[-]");
+    assert_eq!(compile("+++[+]>").simplify().to_string(), "+++[-]>");
+    let dec = (0 .. 255).map(|_| "+").collect::<String>();
+    assert_eq!(compile(&dec).simplify().to_string(), "-")
+}
+
+#[test]
+fn test_pretty() {
+    let vm = compile("++[>+[-]<-]>.");
+    assert_eq!(vm.pretty(None), "++\n[\n    >+\n    [\n        -\n    ]\n    <-\n]\n>.\n");
+    assert_eq!(compile(&vm.pretty(Some(4))), vm);
+    assert_eq!(compile("+++++.").pretty(Some(2)), "++\n++\n+.\n");
+    let vm = compile("#pbrain+host +(-):%");
+    assert_eq!(vm.pretty(None), "#host+pbrain\n+\n(\n    -\n)\n:%\n");
+    assert_eq!(compile(&vm.pretty(None)), vm)
+}
+
+#[test]
+fn test_image() {
+    let vm = Vm::print(b"hello, world");
+    let bytes = vm.encode(None).unwrap();
+    assert!(bytes.len() < vm.to_string().len() / 2);
+    assert_eq!(Vm::decode(&bytes).unwrap(), (vm, None));
+    let src = "+ + [-] .";
+    let map = image::source_map(src).unwrap();
+    let bytes = compile("++[-].").encode(Some(&map)).unwrap();
+    assert_eq!(Vm::decode(&bytes).unwrap().1, Some(vec![ 0, 2, 4, 5, 6, 8 ]));
+    assert!(Vm::decode(&bytes[.. bytes.len() - 1]).is_err());
+    assert!(compile("++[-].").encode(Some(&map[1 ..])).is_err());
+    assert!(compile("++").encode(Some(&[ 1, 0 ])).is_err());
+    // a single run repeated far more often than the image could mean
+    let mut huge = image::MAGIC.to_vec();
+    huge.extend_from_slice(&[ image::VERSION, 0, 1, b'+', 0xFF, 0xFF, 0xFF, 0xFF, 0x0F ]);
+    assert!(Vm::decode(&huge).is_err());
+    let mut runs = image::MAGIC.to_vec();
+    runs.extend_from_slice(&[ image::VERSION, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, b'+', 1 ]);
+    assert!(Vm::decode(&runs).is_err());
+    // offsets adding up past the largest number
+    let mut offsets = image::MAGIC.to_vec();
+    offsets.extend_from_slice(&[ image::VERSION, 1, 1, b'+', 2 ]);
+    for _ in 0 .. 2 {
+        offsets.extend_from_slice(&[ 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01 ])
+    }
+    assert!(Vm::decode(&offsets).is_err())
+}
+
+#[test]
+fn test_image_source_map() {
+    let src = "#pbrain+host +(-)%";
+    let map = image::source_map(src).unwrap();
+    assert_eq!(map, vec![ 13, 14, 15, 16, 17 ]);
+    let path = ::std::env::temp_dir().join(format!("bf-test-{}.img", ::std::process::id()));
+    compile(src).save_with_map(&path, Some(&map)).unwrap();
+    let loaded = Vm::load_with_map(&path);
+    ::std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), (compile(src), Some(map)));
+    assert_eq!(image::source_map("#ook Ook. Ook.  Ook! Ook!").unwrap(), vec![ 5, 16 ]);
+    assert!(image::source_map("#nope +").is_err())
+}
+
+#[test]
+fn test_dialects() {
+    let src = "++[>+<-]>.";
+    let ook = Dialect::ook().emit(&compile(src)).unwrap();
+    assert!(ook.starts_with("Ook. Ook. Ook. Ook. Ook! Ook? Ook. Ook?"));
+    assert_eq!(compile(&format!("#ook {}", ook)).to_string(), src);
+    let blub = Dialect::blub().emit(&compile(src)).unwrap();
+    assert_eq!(Dialect::blub().parse(&blub).unwrap().to_string(), src);
+    let alias = Dialect::alias("x", [ "r", "l", "i", "d", "o", "i", "(", ")" ], " ");
+    assert_eq!(alias.parse("ii ( r i l d ) r o").unwrap().to_string(), src);
+    assert!(Dialect::ook().parse("Ook. Ook").is_err());
+    assert!(Dialect::ook().emit(&compile("#pbrain +(-)")).is_err());
+    assert_eq!(Dialect::pbrain().emit(&compile("#pbrain +(-)")).unwrap(), "+(-)");
+    // tokens which are prefixes of others
+    let prefix = Dialect::alias("y", [ "a", "aa", "b", "bb", "c", "cc", "d", "dd" ], "");
+    assert_eq!(prefix.parse("aabaaa").unwrap().to_string(), "<+<>");
+    assert!(<Result<_, _>>::from(Convert::from("#klingon +")).is_err())
+}
+
+#[test]
+fn test_pbrain() {
+    assert!(<Result<_, _>>::from(Convert::from("+(-):")).is_err());
+    // procedure 1 doubles the current cell into the next one
+    let vm = compile("#pbrain +([->++<]>):<+:.");
+    assert_eq!(vm.eval(b"", None).unwrap(), vec![ 4 ]);
+    assert_eq!(vm.to_string(), "#pbrain +([->++<]>):<+:.");
+    assert_eq!(Vm::decode(&vm.encode(None).unwrap()).unwrap().0, vm);
+    let recursive = compile("#pbrain (:):");
+    assert_eq!(recursive.eval(b"", None).unwrap_err(), "call stack overflow");
+    assert!(compile("#pbrain +:").eval(b"", None).is_err());
+    assert!(compile("#pbrain ([)]").eval(b"", None).is_err())
+}
+
+struct Doubler(usize);
+
+impl Host for Doubler {
+    fn call(&mut self, window: &mut [u8])->Result<(), String> {
+        self.0 += 1;
+        assert_eq!(window.len(), HOST_WINDOW);
+        window[1] = window[0] * 2;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_host_call() {
+    assert!(<Result<_, _>>::from(Convert::from("%")).is_err());
+    let vm = compile("#host +++%>.");
+    assert_eq!(vm.to_string(), "#host +++%>.");
+    let mut host = Doubler(0);
+    assert_eq!(vm.eval_hosted(b"", None, &mut host).unwrap(), vec![ 6 ]);
+    assert_eq!(host.0, 1);
+    assert!(vm.eval(b"", None).is_err());
+    assert_eq!(compile("#pbrain+host (%)").to_string(), "#host+pbrain (%)")
+}
+
+#[test]
+fn test_fork() {
+    assert!(compile("#fork Y").eval(b"", None).is_err());
+    assert_eq!(compile("#fork Y+++.").eval_forked(b"", None, 2).unwrap(), vec![ 3, 4 ]);
+    // parent, first child, its child, then the second child of the parent
+    assert_eq!(compile("#fork YY.").eval_forked(b"", None, 4).unwrap(), vec![ 0, 0, 1, 1 ]);
+    assert_eq!(compile("#fork ,Y,.").eval_forked(b"ab", None, 2).unwrap(), b"bb".to_vec());
+    let bomb = compile("#fork +[Y]");
+    assert_eq!(bomb.eval_forked(b"", None, 8).unwrap_err(), "thread limit exceeded");
+    assert!(compile("#fork Y+[]").eval_forked(b"", Some(100), 2).is_err());
+    // a failing thread stops the looping one, whether parent or child
+    assert_eq!(compile("#fork Y[+[]]<").eval_forked(b"", None, 2).unwrap_err(), "illegal pointer movement");
+    assert_eq!(compile("#fork Y[<<]+[]").eval_forked(b"", None, 2).unwrap_err(), "illegal pointer movement")
+}
+
+#[test]
+fn test_testing() {
+    use testing::*;
+    let src = "# echo\nprogram ,[.,]\ncase \"ab\\x00\" -> \"ab\"\ncase \"a\" -> error \"exhausted\"\n\
+               case \"a\" -> \"b\"\n\nmacro add_one\ncase args \"a\" -> \"l1:be\" steps 100\n";
+    let suites = parse(src).unwrap();
+    assert_eq!(suites.len(), 2);
+    let vm = compile(",[.,]");
+    assert_eq!(suites[0].subject, Subject::Program(vm.clone()));
+    let outcomes = suites[0].cases.iter().map(|x| x.run(&vm)).collect::<Vec<_>>();
+    assert_eq!(outcomes, vec![ Outcome::Pass, Outcome::Pass, Outcome::Fail(Err("input exhausted".to_string())) ]);
+    let case = &suites[1].cases[0];
+    assert_eq!((case.line, &case.input[..], case.max_steps), (8, &b"l1:ae"[..], Some(100)));
+    assert_eq!(case.run(&Vm::add_one()), Outcome::Pass);
+    assert!(parse("case \"\" -> \"\"").is_err());
+    assert!(parse("program +\ncase \"\" \"\"").is_err());
+    assert!(parse("program +\ncase \"\\x+F\" -> \"\"").is_err())
+}
+
+#[test]
+fn test_call() {
+    use bencode::Value;
+    let arg = Value::ByteString(b"a".to_vec());
+    let ret = call(&Vm::add_one(), &[ arg ]).unwrap();
+    assert_eq!(ret, Value::List(vec![ Value::ByteString(b"b".to_vec()) ]));
+    match call(&Vm::add_one(), &[]) {
+        Err(CallError::Run(err)) => assert_eq!(err, "input exhausted"),
+        _ => panic!("running out of arguments should fail")
+    }
+    match call(&Vm::print(b"1:"), &[]) {
+        Err(CallError::Return(output, _)) => assert_eq!(output, b"1:"),
+        _ => panic!("truncated return value should fail")
+    }
+}
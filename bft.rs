@@ -0,0 +1,90 @@
+extern crate bf;
+extern crate rt;
+extern crate repl;
+
+use std::env::args;
+use std::fs::File;
+use std::io::Read;
+use std::process::exit;
+use bf::testing::{self, Subject, Expect, Outcome};
+use repl::BfVm;
+use repl::utils::pretty;
+use rt::{Vm, MacroResult};
+
+fn show(rslt: &Result<Vec<u8>, String>)->String {
+    match rslt {
+        &Ok(ref x) => format!("\"{}\"", pretty(x)),
+        &Err(ref err) => format!("error {:?}", err)
+    }
+}
+
+fn run_file(path: &str, vm: &mut BfVm)->Result<(usize, usize), String> {
+    let mut src = String::new();
+    match File::open(path) {
+        Ok(mut f) => if let Err(err) = f.read_to_string(&mut src) {
+            return Err(format!("{}: {}", path, err))
+        },
+        Err(err) => return Err(format!("{}: {}", path, err))
+    }
+    let (mut passed, mut failed) = (0, 0);
+    for suite in try!(testing::parse(&src).map_err(|err| format!("{}: {}", path, err))) {
+        let code = match suite.subject {
+            Subject::Program(code) => code,
+            Subject::Macro(name) => match vm.macro_expand(&name) {
+                MacroResult::Ok(code) => code,
+                MacroResult::Err(err) => return Err(format!("{}: {}", path, err)),
+                _ => return Err(format!("{}: macro `{}` is not a lambda", path, name))
+            }
+        };
+        for case in suite.cases {
+            match case.run_hosted(&code, vm) {
+                Outcome::Pass => {
+                    println!("ok   {}:{}", path, case.line);
+                    passed += 1
+                },
+                Outcome::Fail(actual) => {
+                    println!("FAIL {}:{}", path, case.line);
+                    println!("    input:    \"{}\"", pretty(&case.input));
+                    let expected = match case.expect {
+                        Expect::Output(x) => Ok(x),
+                        Expect::Error(x) => Err(x)
+                    };
+                    println!("    expected: {}", show(&expected));
+                    println!("    actual:   {}", show(&actual));
+                    if let (&Ok(ref x), &Ok(ref y)) = (&expected, &actual) {
+                        let at = x.iter().zip(y.iter()).take_while(|&(a, b)| a == b).count();
+                        println!("    first difference at byte {}", at)
+                    }
+                    failed += 1
+                }
+            }
+        }
+    }
+    Ok((passed, failed))
+}
+
+pub fn main() {
+    let paths = args().skip(1).collect::<Vec<_>>();
+    if paths.is_empty() {
+        eprintln!("usage: bft <file.bft>...");
+        exit(2)
+    }
+    let mut vm = BfVm::default();
+    let (mut passed, mut failed) = (0, 0);
+    for path in &paths {
+        match run_file(path, &mut vm) {
+            Ok((p, f)) => {
+                passed += p;
+                failed += f
+            },
+            Err(err) => {
+                eprintln!("error: {}", err);
+                exit(2)
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        exit(1)
+    }
+}
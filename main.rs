@@ -2,15 +2,24 @@ extern crate bf;
 extern crate rt;
 extern crate bencode;
 
-use std::sync::mpsc::channel;
+use std::collections::{HashMap, HashSet};
 use std::default::Default;
+use std::env::args;
+use std::fs::File;
+use std::io::{stdin, Read};
+use std::process::exit;
 use std::fmt::{Formatter, Error, Display};
-use rt::MacroResult;
+use rt::{Vm, MacroResult};
 use rt::MacroResult as Macro;
 
+const PRETTY_WIDTH: usize = 72;
 
 #[derive(Default)]
-struct BfVm {
+pub struct BfVm {
+    vars: HashMap<String, rt::Val<BfVm>>,
+    /// code of the lambdas taking their arguments unevaluated, marked with `@lazy <macro>~`;
+    /// lambdas are told apart by code only, so any other lambda with the same code is lazy too
+    lazy: HashSet<bf::Vm>,
     log_macros: Vec<(String, bf::Vm)>,
     log_calls: Vec<(bf::Vm, Vec<rt::Val<BfVm>>, rt::Val<BfVm>)>
 }
@@ -23,7 +32,7 @@ impl Display for BfVm {
         } else {
             try!(write!(f, "log for macros: ({} entries)\n", cnt_macros));
             for &(ref k, ref v) in &self.log_macros {
-                try!(write!(f, "!{}=`{}'\n", k, v));
+                try!(write!(f, "!{}=\n{}", k, v.pretty(Some(PRETTY_WIDTH))));
             }
         }
         let cnt_calls = self.log_calls.len();
@@ -32,11 +41,11 @@ impl Display for BfVm {
         } else {
             try!(write!(f, "log for calls: ({} entries)\n", cnt_calls));
             for &(ref code, ref args, ref rslt) in &self.log_calls {
-                try!(write!(f, "`{}'\n", code));
+                try!(write!(f, "{}", code.pretty(Some(PRETTY_WIDTH))));
                 for (idx, arg) in (1 ..).zip(args.iter()) {
-                    try!(write!(f, "arg{}: {:?}\n", idx, arg.to_string()));
+                    try!(write!(f, "arg{}: {}\n", idx, arg.to_source()));
                 }
-                try!(write!(f, "result: {:?}", rslt));
+                try!(write!(f, "result: {}\n", rslt.to_source()));
             }
         }
         Ok(())
@@ -53,8 +62,8 @@ impl rt::Vm for BfVm {
             "A" => Macro::Ok(bf::Vm::print(b"1:A")),
             "add_one" => Macro::Ok(bf::Vm::add_one()),
             "log" => {
-                let log = self.to_string();
-                Macro::Ok(bf::Vm::print(&bencode::byte_string(log.as_bytes())))
+                print!("{}", self);
+                return Macro::Continue
             },
             "help" => {
                 Macro::Continue
@@ -62,21 +71,53 @@ impl rt::Vm for BfVm {
             "quit" => {
                 Macro::Quit
             },
+            x if x.starts_with("shortest ") => match search(&x["shortest ".len() ..]) {
+                Some(vm) => Macro::Ok(vm),
+                None => return Macro::Err(format!("no lambda within {} instructions and {} candidates",
+                                                  SEARCH_LEN, SEARCH_NODES))
+            },
+            x if x.starts_with("load ") => match bf::Vm::load(&x["load ".len() ..]) {
+                Ok(vm) => Macro::Ok(vm),
+                Err(err) => return Macro::Err(format!("failed to load lambda: {}", err))
+            },
+            x if x.starts_with("lazy ") => {
+                let name = &x["lazy ".len() ..];
+                match self.macro_expand(name) {
+                    Macro::Ok(code) => {
+                        self.lazy.insert(code);
+                    },
+                    Macro::Err(err) => return Macro::Err(err),
+                    _ => println!("usage: `@lazy <macro name>~`")
+                }
+                return Macro::Continue
+            },
+            x if x.starts_with("save ") => {
+                let mut params = x["save ".len() ..].splitn(2, ' ');
+                let entry = params.next().and_then(|x| x.parse::<u8>().ok())
+                                  .and_then(|idx| self.log_macros.get(idx as usize));
+                match (entry, params.next()) {
+                    (Some(&(_, ref code)), Some(path)) => if let Err(err) = code.save(path) {
+                        return Macro::Err(format!("failed to save lambda: {}", err))
+                    },
+                    _ => println!("usage: `@save <macro access log index> <path>~`")
+                }
+                return Macro::Continue
+            },
             x => if let Ok(idx) = x.parse::<u8>() {
                 if let Some(&(ref name, ref code)) = self.log_macros.get(idx as usize) {
-                    println!("!{}=`{}'", name, code)
+                    print!("!{}=\n{}", name, code.pretty(Some(PRETTY_WIDTH)))
                 } else {
                     println!("no macro access log entry for index {}", idx);
-                    println!("type `(@log~)` for log overview")
+                    println!("type `@log~` for log overview")
                 }
                 return Macro::Continue
             } else if let (Some(&b'#'), Ok(idx)) =
                 (x.as_bytes().first(), x.chars().skip(1).collect::<String>().parse::<u8>()) {
                 if let Some(&(ref code, ref args, ref rslt)) = self.log_calls.get(idx as usize) {
-                    println!("`{}'\nargs: {:?}\nresult: {:?}", code, args, rslt)
+                    println!("{}args: {:?}\nresult: {:?}", code.pretty(Some(PRETTY_WIDTH)), args, rslt)
                 } else {
                     println!("no function call log entry for index #{}", idx);
-                    println!("type `(@log~)` for log overview")
+                    println!("type `@log~` for log overview")
                 }
                 return Macro::Continue
             } else {
@@ -93,40 +134,189 @@ impl rt::Vm for BfVm {
         ret
     }
     fn run(&mut self, code: &bf::Vm, args: &Vec<rt::Val<Self>>)->Result<rt::Val<Self>, String> {
-        let (output, data) = channel();
-        let (arg_stream, input) = channel();
-        arg_stream.send(b'l').unwrap();
-        for i in args {
-            for b in utils::rt2bencode(rt::Val::from(i)) {
-                arg_stream.send(b).unwrap()
-            }
-        }
-        arg_stream.send(b'e').unwrap();
-
-        if let Err(err) = code.run(output, input) {
-            return Err(format!("failed to start vm: {:?}", err))
-        }
-        let mut ret = Vec::new();
-        while let Ok(n) = data.recv() {
-            ret.push(n)
-        }
-        match bencode::parse(&mut ret.iter().cloned()) {
+        let values = args.iter().map(|x| utils::rt2value(rt::Val::from(x))).collect::<Vec<_>>();
+        match bf::call_hosted(code, &values, self) {
             Ok(s) => {
+                let rslt = try!(utils::bencode2rt(s));
                 self.log_calls.push((code.clone(),
                                      args.iter().map(rt::Val::from).collect(),
-                                     utils::bencode2rt(s.clone())));
-                Ok(utils::bencode2rt(s))
+                                     rt::Val::from(&rslt)));
+                Ok(rslt)
             },
-            Err(err) => {
+            Err(bf::CallError::Run(err)) => Err(format!("lambda failed: {}", err)),
+            Err(bf::CallError::Return(ret, err)) => {
                 let fmt = utils::pretty(&ret);
-                Err(format!("broken return value {:?}, {:?}", fmt, err))
+                Err(format!("broken return value \"{}\", {:?}", fmt, err))
             }
         }
     }
+    fn strategy(&mut self, code: &bf::Vm)->rt::Strategy {
+        if self.lazy.contains(code) {
+            rt::Strategy::ByName
+        } else {
+            rt::Strategy::Eager
+        }
+    }
+    fn store(&mut self, name: &str, v: rt::Val<Self>) {
+        self.vars.insert(name.to_string(), v);
+    }
+    fn fetch(&mut self, name: &str)->Option<rt::Val<Self>> {
+        self.vars.get(name).map(rt::Val::from)
+    }
+}
+
+/// `%` with the current cell set to this logs the following cells as text, to stderr
+const HOST_LOG: u8 = 0;
+/// `%` with the current cell set to this expands the macro named by the following cells,
+/// calls it without arguments and writes the resulting string back in their place
+const HOST_MACRO: u8 = 1;
+/// `%` with the current cell set to this writes the string stored in the variable
+/// named by the following cells back in their place
+const HOST_FETCH: u8 = 2;
+
+impl bf::Host for BfVm {
+    fn call(&mut self, window: &mut [u8])->Result<(), String> {
+        let arg = window[1 ..].iter().cloned().take_while(|&b| b != 0).collect::<Vec<_>>();
+        match window[0] {
+            HOST_LOG => {
+                eprintln!("debug: {}", utils::pretty(&arg));
+                Ok(())
+            },
+            HOST_MACRO => {
+                let name = String::from_utf8_lossy(&arg).into_owned();
+                let code = match self.macro_expand(&name) {
+                    Macro::Ok(code) => code,
+                    Macro::Err(err) => return Err(err),
+                    _ => return Err(format!("macro `{}` is not a lambda", name))
+                };
+                let rslt = try!(self.run(&code, &Vec::new()));
+                write_back(window, rslt)
+            },
+            HOST_FETCH => {
+                let name = String::from_utf8_lossy(&arg).into_owned();
+                match self.fetch(&name) {
+                    Some(v) => write_back(window, v),
+                    None => Err(format!("variable `{}` not defined", name))
+                }
+            },
+            x => Err(format!("unknown host service {}", x))
+        }
+    }
+}
+
+fn write_back(window: &mut [u8], v: rt::Val<BfVm>)->Result<(), String> {
+    let rslt = match v {
+        rt::Val::Str(s) => s.into_bytes(),
+        rt::Val::Nil => Vec::new(),
+        v => return Err(format!("string needed, found {} instead", v))
+    };
+    if rslt.len() > window.len() - 1 {
+        return Err(format!("{} bytes do not fit in the {} cells after the host call",
+                           rslt.len(), window.len() - 1))
+    }
+    for (i, cell) in window[1 ..].iter_mut().enumerate() {
+        *cell = rslt.get(i).cloned().unwrap_or(0)
+    }
+    Ok(())
+}
+
+const SEARCH_LEN: usize = 8;
+const SEARCH_STEPS: usize = 1000;
+/// partial programs `@shortest` tries before giving up, keeping the repl responsive
+const SEARCH_NODES: usize = 200000;
+
+/// Find the shortest lambda for examples like `a=b;x,y=z`,
+/// each mapping comma separated string arguments to a string result.
+fn search(spec: &str)->Option<bf::Vm> {
+    let mut examples = Vec::new();
+    for case in spec.split(';') {
+        let mut sides = case.splitn(2, '=');
+        let (args, rslt) = match (sides.next(), sides.next()) {
+            (Some(args), Some(rslt)) => (args, rslt),
+            _ => return None
+        };
+        let mut input = vec![ b'l' ];
+        for arg in args.split(',').filter(|x| !x.is_empty()) {
+            input.extend(bencode::byte_string(arg.as_bytes()))
+        }
+        input.push(b'e');
+        examples.push((input, bencode::byte_string(rslt.as_bytes())))
+    }
+    bf::search::shortest(&examples, SEARCH_LEN, SEARCH_STEPS, SEARCH_NODES)
+}
+
+fn usage()->! {
+    eprintln!("usage: repl [--quiet] [--legacy-strings] [-e <expr> | <script> | -]");
+    exit(2)
+}
+
+/// Evaluate a whole script, printing each result unless `quiet`,
+/// and exit with a failure at the first error.
+fn run_script(name: &str, src: &str, syn: rt::Syntax, quiet: bool) {
+    let mut vm = BfVm::default();
+    match rt::eval_all(&mut vm, src, syn, &mut |v| if !quiet { println!("{}", v) }) {
+        Ok(()) => (),
+        Err(rt::ScriptError::Parse(err)) => {
+            eprintln!("{}:{}: {:?}", name, err.span.start, err.kind);
+            eprint!("{}", rt::caret(src, &err.span));
+            exit(1)
+        },
+        Err(rt::ScriptError::Eval(err)) => {
+            eprintln!("{}: {}", name, err);
+            exit(1)
+        }
+    }
 }
 
 pub fn main() {
-    rt::repl::<BfVm>(&mut Default::default());
+    let mut syn = rt::Syntax::default();
+    let mut quiet = false;
+    let mut script = None;
+    let mut params = args().skip(1);
+    while let Some(arg) = params.next() {
+        let next = match &arg[..] {
+            "-q" | "--quiet" => {
+                quiet = true;
+                continue
+            },
+            "--legacy-strings" => {
+                syn = rt::Syntax::legacy();
+                continue
+            },
+            "-e" => match params.next() {
+                Some(expr) => ("-e".to_string(), expr),
+                None => usage()
+            },
+            "-" => {
+                let mut src = String::new();
+                if let Err(err) = stdin().read_to_string(&mut src) {
+                    eprintln!("error: <stdin>: {}", err);
+                    exit(2)
+                }
+                ("<stdin>".to_string(), src)
+            },
+            x if x.starts_with('-') => usage(),
+            path => {
+                let mut src = String::new();
+                if let Err(err) = File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+                    eprintln!("error: {}: {}", path, err);
+                    exit(2)
+                }
+                (path.to_string(), src)
+            }
+        };
+        if script.is_some() {
+            usage()
+        }
+        script = Some(next)
+    }
+    match script {
+        Some((name, src)) => run_script(&name, &src, syn, quiet),
+        None => rt::repl_with::<BfVm>(&mut Default::default(), syn)
+    }
 }
 
-mod utils;
+pub mod utils;
+
+#[cfg(test)]
+mod tests;
@@ -1,4 +1,6 @@
 use std::rc::Rc;
+use std::iter::Peekable;
+use std::marker::PhantomData;
 use std::fmt::{Formatter, Debug, Display};
 use std::fmt::Error as FmtError;
 
@@ -12,29 +14,129 @@ pub trait Vm: {
     fn run(&mut self, _: &Self::ByteCode, _: &Vec<Val<Self>>)->Result<Val<Self>, String> {
             Err("method `run` not implemented".to_string())
     }
+    /// How `run` receives the arguments of a call to `code`.
+    fn strategy(&mut self, _code: &Self::ByteCode)->Strategy {
+        Strategy::Eager
+    }
+    /// Keep an evaluated value under `name`, for `fetch` to return later.
+    fn store(&mut self, _: &str, _: Val<Self>) {}
+    fn fetch(&mut self, _: &str)->Option<Val<Self>> {
+        None
+    }
+}
+
+/// Evaluation strategy for the arguments of a lambda call.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// evaluate every argument first, left to right
+    Eager,
+    /// pass the arguments as written, unevaluated
+    ByName
 }
 
 pub enum Val<T: Vm + ?Sized> {
     Str(String),
+    /// byte string, for data that is not UTF-8 text
+    Bytes(Vec<u8>),
+    Int(i32),
+    /// list literal, or list of values once evaluated
+    List(Vec<Val<T>>),
+    /// dictionary literal or value, keys in the order written
+    Dict(Vec<(String, Val<T>)>),
     If(Rc<Val<T>>, Rc<Val<T>>, Rc<Val<T>>),
     Lambda(T::ByteCode),
     Call(Rc<Val<T>>, Vec<Val<T>>),
     Macro(String),
+    Quote(Rc<Val<T>>),
+    /// template quoted with `'`, its holes filled in when evaluated
+    Quasi(Rc<Val<T>>),
+    /// hole in a template, written `,expr`
+    Unquote(Rc<Val<T>>),
+    Eval(Rc<Val<T>>),
+    Store(String, Rc<Val<T>>),
+    Fetch(String),
+    Or(Rc<Val<T>>, Rc<Val<T>>),
+    And(Rc<Val<T>>, Rc<Val<T>>),
+    /// `% expr fallback`, the fallback evaluated with the message as `$error~` if `expr` fails
+    Catch(Rc<Val<T>>, Rc<Val<T>>),
+    /// function literal, named parameters and a body
+    Func(Rc<Vec<String>>, Rc<Val<T>>),
+    /// function value, capturing the scope its literal was evaluated in
+    Closure(Rc<Vec<String>>, Rc<Val<T>>, Rc<Env<T>>),
+    /// parsed expression, and where in the source it was read
+    At(Span, Rc<Val<T>>),
     Nil
 }
 use Val::*;
 
+/// Arguments bound by function calls, innermost scope first.
+pub struct Env<T: Vm + ?Sized> {
+    vars: Vec<(String, Val<T>)>,
+    parent: Option<Rc<Env<T>>>
+}
+
+impl<T: Vm> Env<T> {
+    pub fn new()->Env<T> {
+        Env { vars: Vec::new(), parent: None }
+    }
+    pub fn get(&self, name: &str)->Option<&Val<T>> {
+        match self.vars.iter().find(|x| x.0 == name) {
+            Some(x) => Some(&x.1),
+            None => self.parent.as_ref().and_then(|x| x.get(name))
+        }
+    }
+}
+
 impl<T: Vm> Val<T> {
     pub fn kind(&self)->&'static str {
         match self {
             &Str(_) => "str",
+            &Bytes(_) => "bytes",
+            &Int(_) => "int",
+            &List(_) => "list",
+            &Dict(_) => "dict",
             &If(..) => "if",
             &Lambda(_) => "lambda",
             &Call(..) => "call",
             &Macro(_) => "macro",
+            &Quote(_) => "quote",
+            &Quasi(_) => "quasi",
+            &Unquote(_) => "unquote",
+            &Eval(_) => "eval",
+            &Store(..) => "store",
+            &Fetch(_) => "fetch",
+            &Or(..) => "or",
+            &And(..) => "and",
+            &Catch(..) => "catch",
+            &Func(..) => "func",
+            &Closure(..) => "closure",
+            &At(_, ref v) => v.kind(),
             &Nil => "nil"
         }
     }
+    fn has_holes(&self)->bool {
+        match self {
+            &Unquote(_) => true,
+            &At(_, ref v) => v.has_holes(),
+            &If(ref p, ref t, ref f) => p.has_holes() || t.has_holes() || f.has_holes(),
+            &Call(ref first, ref args) => first.has_holes() || args.iter().any(Val::has_holes),
+            &List(ref items) => items.iter().any(Val::has_holes),
+            &Dict(ref items) => items.iter().any(|x| x.1.has_holes()),
+            &Quote(ref v) | &Eval(ref v) | &Store(_, ref v) | &Func(_, ref v) => v.has_holes(),
+            &Or(ref l, ref r) | &And(ref l, ref r) | &Catch(ref l, ref r) => l.has_holes() || r.has_holes(),
+            // holes of a nested template are filled by that template
+            _ => false
+        }
+    }
+    /// Whether branching takes this for true: anything but nil and the empty string.
+    pub fn is_true(&self)->bool {
+        match self {
+            &Nil => false,
+            &Str(ref s) => !s.is_empty(),
+            &Bytes(ref s) => !s.is_empty(),
+            _ => true
+        }
+    }
 }
 
 impl<T> From<Rc<Val<T>>> for Val<T> where T: Vm, T::ByteCode: Clone {
@@ -47,12 +149,28 @@ impl<'a, T> From<&'a Val<T>> for Val<T> where T: Vm, T::ByteCode: Clone {
     fn from(v: &'a Val<T>)->Self {
         match v {
             &Str(ref s) => Str(s.clone()),
+            &Bytes(ref s) => Bytes(s.clone()),
             &If(ref p, ref t, ref f) => If(p.clone(), t.clone(), f.clone()),
             &Lambda(ref bc) => Lambda(bc.clone()),
             &Call(ref first, ref args) => Call(first.clone(), {
                 args.iter().map(Val::from).collect()
             }),
             &Macro(ref s) => Macro(s.clone()),
+            &Quote(ref v) => Quote(v.clone()),
+            &Quasi(ref v) => Quasi(v.clone()),
+            &Unquote(ref v) => Unquote(v.clone()),
+            &Eval(ref v) => Eval(v.clone()),
+            &Store(ref name, ref v) => Store(name.clone(), v.clone()),
+            &Fetch(ref name) => Fetch(name.clone()),
+            &Or(ref l, ref r) => Or(l.clone(), r.clone()),
+            &And(ref l, ref r) => And(l.clone(), r.clone()),
+            &Catch(ref v, ref fallback) => Catch(v.clone(), fallback.clone()),
+            &Func(ref params, ref body) => Func(params.clone(), body.clone()),
+            &Closure(ref params, ref body, ref env) => Closure(params.clone(), body.clone(), env.clone()),
+            &At(span, ref v) => At(span, v.clone()),
+            &Int(n) => Int(n),
+            &List(ref items) => List(items.iter().map(Val::from).collect()),
+            &Dict(ref items) => Dict(items.iter().map(|x| (x.0.clone(), Val::from(&x.1))).collect()),
             &Nil => Nil
         }
     }
@@ -64,6 +182,131 @@ impl<T> Debug for Val<T> where T: Vm, T::ByteCode: Display {
     }
 }
 
+/// Structural equality, ignoring where expressions were parsed from.
+impl<T> PartialEq for Val<T> where T: Vm, T::ByteCode: PartialEq {
+    fn eq(&self, other: &Val<T>)->bool {
+        match (self, other) {
+            (&At(_, ref x), y) => **x == *y,
+            (x, &At(_, ref y)) => *x == **y,
+            (&Str(ref a), &Str(ref b)) => a == b,
+            (&Bytes(ref a), &Bytes(ref b)) => a == b,
+            (&If(ref p, ref t, ref f), &If(ref q, ref u, ref g)) => p == q && t == u && f == g,
+            (&Lambda(ref a), &Lambda(ref b)) => a == b,
+            (&Call(ref a, ref x), &Call(ref b, ref y)) => a == b && x == y,
+            (&Macro(ref a), &Macro(ref b)) => a == b,
+            (&Quote(ref a), &Quote(ref b)) => a == b,
+            (&Quasi(ref a), &Quasi(ref b)) => a == b,
+            (&Unquote(ref a), &Unquote(ref b)) => a == b,
+            (&Eval(ref a), &Eval(ref b)) => a == b,
+            (&Store(ref m, ref a), &Store(ref n, ref b)) => m == n && a == b,
+            (&Fetch(ref a), &Fetch(ref b)) => a == b,
+            (&Or(ref a, ref x), &Or(ref b, ref y)) => a == b && x == y,
+            (&And(ref a, ref x), &And(ref b, ref y)) => a == b && x == y,
+            (&Catch(ref a, ref x), &Catch(ref b, ref y)) => a == b && x == y,
+            (&Func(ref p, ref a), &Func(ref q, ref b)) => p == q && a == b,
+            (&Closure(ref p, ref a, ref e), &Closure(ref q, ref b, ref f)) => {
+                p == q && a == b && Rc::ptr_eq(e, f)
+            },
+            (&Int(a), &Int(b)) => a == b,
+            (&List(ref a), &List(ref b)) => a == b,
+            (&Dict(ref a), &Dict(ref b)) => a == b,
+            (&Nil, &Nil) => true,
+            _ => false
+        }
+    }
+}
+
+// binding strength of the context an expression is written in
+const IN_EXPR: usize = 0;
+const IN_AND: usize = 1;
+const IN_TERM: usize = 2;
+
+impl<T> Val<T> where T: Vm, T::ByteCode: Display {
+    /// Source text which `parse` reads back into an equal tree, for any tree `parse` builds.
+    /// Closures lose their captured scope, and operators where the grammar cannot
+    /// put them are wrapped in `*^...~~`, which evaluates the same.
+    pub fn to_source(&self)->String {
+        self.source(IN_EXPR)
+    }
+    fn source(&self, ctx: usize)->String {
+        fn escape(s: &str, delim: char)->String {
+            let mut ret = String::new();
+            for c in s.chars() {
+                match c {
+                    '\\' => ret.push_str("\\\\"),
+                    '\n' => ret.push_str("\\n"),
+                    '\r' => ret.push_str("\\r"),
+                    '\t' => ret.push_str("\\t"),
+                    c if c == delim => {
+                        ret.push('\\');
+                        ret.push(c)
+                    },
+                    c => ret.push(c)
+                }
+            }
+            ret
+        }
+        // a left operand must not swallow the operator after it
+        let left = |v: &Val<T>, ctx| if v.open_ended() {
+            format!("*^{}~~", v.source(IN_EXPR))
+        } else {
+            v.source(ctx)
+        };
+        match self {
+            &At(_, ref v) => v.source(ctx),
+            &Or(..) if ctx > IN_EXPR => format!("*^{}~~", self.source(IN_EXPR)),
+            &And(..) if ctx > IN_AND => format!("*^{}~~", self.source(IN_EXPR)),
+            &Or(ref l, ref r) => format!("{} | {}", left(l, IN_EXPR), r.source(IN_AND)),
+            &And(ref l, ref r) => format!("{} & {}", left(l, IN_AND), r.source(IN_TERM)),
+            &Nil => "()".to_string(),
+            &Int(n) => n.to_string(),
+            &List(ref items) => {
+                let items = items.iter().map(|x| x.source(IN_EXPR)).collect::<Vec<_>>();
+                format!("[{}]", items.join(" "))
+            },
+            &Dict(ref items) => {
+                let items = items.iter().map(|x| format!("\"{}\" {}", escape(&x.0, '"'), x.1.source(IN_EXPR)));
+                format!("{{{}}}", items.collect::<Vec<_>>().join(" "))
+            },
+            &Str(ref s) => format!("\"{}\"", escape(s, '"')),
+            &Bytes(ref s) => format!("b\"{}\"", escape_bytes(s, Some(b'"'))),
+            &If(ref p, ref t, ref f) => {
+                format!("? {} {} {}", p.source(IN_EXPR), t.source(IN_EXPR), f.source(IN_EXPR))
+            },
+            &Catch(ref v, ref fallback) => format!("% {} {}", v.source(IN_EXPR), fallback.source(IN_EXPR)),
+            &Lambda(ref code) => format!("`{}'", escape(&code.to_string(), '\'')),
+            &Call(ref first, ref args) => {
+                let mut ret = format!("({}", first.source(IN_EXPR));
+                for i in args {
+                    ret.push(' ');
+                    ret.push_str(&i.source(IN_EXPR))
+                }
+                ret.push(')');
+                ret
+            },
+            &Macro(ref name) => format!("@{}~", escape(name, '~')),
+            &Quote(ref v) => format!("^{}~", v.source(IN_EXPR)),
+            &Quasi(ref v) => format!("'{}", v.source(IN_TERM)),
+            &Unquote(ref v) => format!(",{}", v.source(IN_TERM)),
+            &Eval(ref v) => format!("*{}~", v.source(IN_EXPR)),
+            &Store(ref name, ref v) => format!("!{}={}~", escape(name, '='), v.source(IN_EXPR)),
+            &Fetch(ref name) => format!("${}~", escape(name, '~')),
+            &Func(ref params, ref body) | &Closure(ref params, ref body, _) => {
+                let params = params.iter().map(|x| escape(x, '=')).collect::<Vec<_>>();
+                format!("\\{}={}~", params.join(" "), body.source(IN_EXPR))
+            }
+        }
+    }
+    // whether the source ends in an expression that would take a following operator in
+    fn open_ended(&self)->bool {
+        match self {
+            &If(..) | &Catch(..) => true,
+            &At(_, ref v) | &Quasi(ref v) | &Unquote(ref v) | &Or(_, ref v) | &And(_, ref v) => v.open_ended(),
+            _ => false
+        }
+    }
+}
+
 impl<T> Display for Val<T> where T: Vm, T::ByteCode: Display {
     fn fmt(&self, f: &mut Formatter)->Result<(), FmtError> {
         fn filter(input: &str, delim: char)->String {
@@ -79,7 +322,29 @@ impl<T> Display for Val<T> where T: Vm, T::ByteCode: Display {
         }
         match self {
             &Nil => write!(f, "nil"),
+            &Int(n) => write!(f, "{}", n),
+            &Bytes(ref s) => write!(f, "{}", pretty(s)),
+            &List(ref items) => {
+                let items = items.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                write!(f, "[{}]", items.join(", "))
+            },
+            &Dict(ref items) => {
+                let items = items.iter().map(|x| format!("{}: {}", x.0, x.1)).collect::<Vec<_>>();
+                write!(f, "{{{}}}", items.join(", "))
+            },
+            &At(_, ref v) => write!(f, "{}", v),
             &Macro(ref name) => write!(f, "@{}~", name),
+            &Quote(ref v) => write!(f, "^{}~", v),
+            &Quasi(ref v) => write!(f, "'{}", v),
+            &Unquote(ref v) => write!(f, ",{}", v),
+            &Eval(ref v) => write!(f, "*{}~", v),
+            &Store(ref name, ref v) => write!(f, "!{}={}~", name, v),
+            &Fetch(ref name) => write!(f, "${}~", name),
+            &Or(ref l, ref r) => write!(f, "{} | {}", l, r),
+            &And(ref l, ref r) => write!(f, "{} & {}", l, r),
+            &Func(ref params, ref body) | &Closure(ref params, ref body, _) => {
+                write!(f, "\\{}={}~", params.join(" "), body)
+            },
             &Str(ref s) => {
                 let mut fmt = String::new();
                 for c in s.chars() {
@@ -88,6 +353,7 @@ impl<T> Display for Val<T> where T: Vm, T::ByteCode: Display {
                 write!(f, "{}", fmt)
             }
             &If(..) => write!(f, "<if expression>"),
+            &Catch(..) => write!(f, "<catch expression>"),
             &Lambda(ref byte_code) => write!(f, "`{}'", filter(&byte_code.to_string(), '\'')),
             &Call(ref byte_code, ref args) => {
                 let mut print_args = String::new();
@@ -100,20 +366,77 @@ impl<T> Display for Val<T> where T: Vm, T::ByteCode: Display {
     }
 }
 
-pub enum Error {
+/// Bytes as text, printable ASCII as is and anything else escaped like in `b"..."`.
+pub fn pretty(s: &[u8])->String {
+    escape_bytes(s, None)
+}
+
+// the body of a `b"..."` literal, escaping `delim` too
+fn escape_bytes(s: &[u8], delim: Option<u8>)->String {
+    let mut ret = String::new();
+    for &i in s {
+        match i {
+            b'\\' => ret.push_str("\\\\"),
+            b'\n' => ret.push_str("\\n"),
+            b'\r' => ret.push_str("\\r"),
+            b'\t' => ret.push_str("\\t"),
+            i if Some(i) == delim => {
+                ret.push('\\');
+                ret.push(i as char)
+            },
+            b' ' ..= b'~' => ret.push(i as char),
+            _ => ret.push_str(&format!("\\x{:02X}", i))
+        }
+    }
+    ret
+}
+
+/// Where a character was read: byte offset, and line and column counted from 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pos {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+/// The source text an expression or error covers, `end` excluded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos
+}
+
+impl Display for Pos {
+    fn fmt(&self, f: &mut Formatter)->Result<(), FmtError> {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+pub enum ErrorKind {
     Char(char),
     Compile(String),
     Eof,
-    Nothing
+    Nothing,
+    /// integer literal not fitting an `i32`
+    Overflow(String),
+    /// key written twice in a dictionary literal
+    Duplicate(String)
 }
-use Error::Char as UnexpectedChar;
-use Error::Compile as CompileError;
-use Error::{Eof, Nothing};
+use ErrorKind::Char as UnexpectedChar;
+use ErrorKind::Compile as CompileError;
+use ErrorKind::{Eof, Nothing};
 
-impl Debug for Error {
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Span
+}
+
+impl Debug for ErrorKind {
     fn fmt(&self, f: &mut Formatter)->Result<(), FmtError> {
         match self {
             &Nothing => write!(f, "nothing to parse"),
+            &ErrorKind::Overflow(ref s) => write!(f, "integer `{}` out of range", s),
+            &ErrorKind::Duplicate(ref s) => write!(f, "duplicate key {:?}", s),
             &CompileError(ref s) => write!(f, "failed to compile: {}", s),
             &UnexpectedChar(c) => write!(f,
                                "unexpected character `{}`",
@@ -123,6 +446,11 @@ impl Debug for Error {
     }
 }
 
+impl Debug for Error {
+    fn fmt(&self, f: &mut Formatter)->Result<(), FmtError> {
+        write!(f, "{:?} at {}", self.kind, self.span.start)
+    }
+}
 enum CalcResult<T> {
     Ok(T),
     Err(String),
@@ -148,10 +476,45 @@ impl<T> CalcResult<T> {
     }
 }
 
-impl<T> Val<T> where T: Vm, T::ByteCode: Display + Clone {
+impl<T> Val<T> where
+    T: Vm,
+    T::Convert: From<String>,
+    String: From<T::CompileFail>,
+    Result<T::ByteCode, T::CompileFail>: From<T::Convert>,
+    T::ByteCode: Display + Clone {
+    #[cfg(test)]
     fn calc(&self, vm: &mut T)->CalcResult<Val<T>> {
+        self.calc_with(vm, Syntax::default())
+    }
+    // `syn` is what strings evaluated with `*...~` are read with
+    fn calc_with(&self, vm: &mut T, syn: Syntax)->CalcResult<Val<T>> {
+        self.calc_in(vm, &Rc::new(Env::new()), syn)
+    }
+    fn calc_in(&self, vm: &mut T, env: &Rc<Env<T>>, syn: Syntax)->CalcResult<Val<T>> {
         match self {
-            &Nil | &Lambda(_) | &Str(_) => Calc::Ok(Val::from(self)),
+            &Nil | &Lambda(_) | &Str(_) | &Bytes(_) | &Int(_) | &Quote(_) | &Closure(..) => Calc::Ok(Val::from(self)),
+            &Func(ref params, ref body) => Calc::Ok(Closure(params.clone(), body.clone(), env.clone())),
+            &List(ref items) => {
+                let mut ret = Vec::new();
+                for i in items {
+                    match i.calc_in(vm, env, syn) {
+                        Calc::Ok(x) => ret.push(x),
+                        other => return other
+                    }
+                }
+                Calc::Ok(List(ret))
+            },
+            &Dict(ref items) => {
+                let mut ret = Vec::new();
+                for &(ref k, ref v) in items {
+                    match v.calc_in(vm, env, syn) {
+                        Calc::Ok(x) => ret.push((k.clone(), x)),
+                        other => return other
+                    }
+                }
+                Calc::Ok(Dict(ret))
+            },
+            &At(_, ref v) => v.calc_in(vm, env, syn),
             &Macro(ref name) => match vm.macro_expand(name) {
                 MacroResult::Ok(x) => Calc::Ok(Lambda(x)),
                 MacroResult::Err(err) => Calc::Err(err),
@@ -159,10 +522,38 @@ impl<T> Val<T> where T: Vm, T::ByteCode: Display + Clone {
                 MacroResult::Quit => Calc::Quit
             },
             &Call(ref first, ref tail) => {
-                match first.calc(vm) {
-                    Calc::Ok(Lambda(ref lambda)) => match vm.run(lambda, tail) {
-                        Ok(x) => Calc::Ok(x),
-                        Err(err) => Calc::Err(format!("runtime error: {}", err))
+                match first.calc_in(vm, env, syn) {
+                    Calc::Ok(Lambda(ref lambda)) => {
+                        let args = match vm.strategy(lambda) {
+                            Strategy::Eager => {
+                                let mut args = Vec::new();
+                                for i in tail {
+                                    match i.calc_in(vm, env, syn) {
+                                        Calc::Ok(x) => args.push(x),
+                                        other => return other
+                                    }
+                                }
+                                args
+                            },
+                            Strategy::ByName => tail.iter().map(Val::from).collect()
+                        };
+                        match vm.run(lambda, &args) {
+                            Ok(x) => Calc::Ok(x),
+                            Err(err) => Calc::Err(format!("runtime error: {}", err))
+                        }
+                    },
+                    Calc::Ok(Closure(ref params, ref body, ref captured)) => {
+                        if params.len() != tail.len() {
+                            return Calc::Err(format!("{} arguments needed, found {}", params.len(), tail.len()))
+                        }
+                        let mut scope = Env { vars: Vec::new(), parent: Some(captured.clone()) };
+                        for (name, arg) in params.iter().zip(tail.iter()) {
+                            match arg.calc_in(vm, env, syn) {
+                                Calc::Ok(x) => scope.vars.push((name.clone(), x)),
+                                other => return other
+                            }
+                        }
+                        body.calc_in(vm, &Rc::new(scope), syn)
                     },
                     Calc::Ok(v) => {
                         Calc::Err(format!("callable needed, found {} instead", v))
@@ -171,22 +562,112 @@ impl<T> Val<T> where T: Vm, T::ByteCode: Display + Clone {
                     quit @ Calc::Quit => quit
                 }
             },
+            &Quasi(ref template) => match template.fill(vm, env, syn) {
+                Ok(x) => Calc::Ok(Quote(Rc::new(x))),
+                Err(other) => other
+            },
+            &Unquote(_) => Calc::Err("unquote outside of a quasiquote".to_string()),
+            &Eval(ref v) => match v.calc_in(vm, env, syn) {
+                Calc::Ok(Quote(ref quoted)) => quoted.calc_in(vm, env, syn),
+                Calc::Ok(Str(ref code)) => match parse_with::<T, _>(&mut Reader::new(code.chars()), syn) {
+                    Ok(ref x) => x.calc_in(vm, env, syn),
+                    Err(err) => Calc::Err(format!("failed to parse {:?}: {:?}", code, err))
+                },
+                other => other
+            },
+            &Store(ref name, ref v) => match v.calc_in(vm, env, syn) {
+                Calc::Ok(x) => {
+                    vm.store(name, Val::from(&x));
+                    Calc::Ok(x)
+                },
+                other => other
+            },
+            &Fetch(ref name) => match env.get(name).map(Val::from).or_else(|| vm.fetch(name)) {
+                Some(x) => Calc::Ok(x),
+                None => Calc::Err(format!("variable `{}` not defined", name))
+            },
+            // the left value decides, and is also the result if it decides alone
+            &Or(ref l, ref r) => match l.calc_in(vm, env, syn) {
+                Calc::Ok(ref x) if x.is_true() => Calc::Ok(Val::from(x)),
+                Calc::Ok(_) => r.calc_in(vm, env, syn),
+                other => other
+            },
+            &And(ref l, ref r) => match l.calc_in(vm, env, syn) {
+                Calc::Ok(ref x) if x.is_true() => r.calc_in(vm, env, syn),
+                other => other
+            },
+            &Catch(ref v, ref fallback) => match v.calc_in(vm, env, syn) {
+                Calc::Err(err) => {
+                    let scope = Env { vars: vec![ ("error".to_string(), Str(err)) ], parent: Some(env.clone()) };
+                    fallback.calc_in(vm, &Rc::new(scope), syn)
+                },
+                other => other
+            },
             &If(ref p, ref t, ref f) => {
-                let p = match p.calc(vm) {
-                    Calc::Ok(Nil) => false,
-                    Calc::Ok(Str(ref s)) if s.is_empty() => false,
-                    Calc::Ok(_) => true,
+                let p = match p.calc_in(vm, env, syn) {
+                    Calc::Ok(ref x) => x.is_true(),
                     err @ Calc::Err(_) => return err,
                     quit @ Calc::Quit => return quit
                 };
                 if p {
-                    t.calc(vm)
+                    t.calc_in(vm, env, syn)
                 } else {
-                    f.calc(vm)
+                    f.calc_in(vm, env, syn)
                 }
             }
         }
     }
+    // copy a template, replacing its holes with the values of their expressions
+    fn fill(&self, vm: &mut T, env: &Rc<Env<T>>, syn: Syntax)->Result<Val<T>, CalcResult<Val<T>>> {
+        fn fill_rc<T>(v: &Rc<Val<T>>, vm: &mut T, env: &Rc<Env<T>>, syn: Syntax)->Result<Rc<Val<T>>, CalcResult<Val<T>>> where
+            T: Vm,
+            T::Convert: From<String>,
+            String: From<T::CompileFail>,
+            Result<T::ByteCode, T::CompileFail>: From<T::Convert>,
+            T::ByteCode: Display + Clone {
+            v.fill(vm, env, syn).map(Rc::new)
+        }
+        Ok(match self {
+            &Unquote(ref v) => match v.calc_in(vm, env, syn) {
+                Calc::Ok(x) => x,
+                other => return Err(other)
+            },
+            &If(ref p, ref t, ref f) => {
+                If(try!(fill_rc(p, vm, env, syn)), try!(fill_rc(t, vm, env, syn)), try!(fill_rc(f, vm, env, syn)))
+            },
+            &Call(ref first, ref args) => {
+                let first = try!(fill_rc(first, vm, env, syn));
+                let mut filled = Vec::new();
+                for i in args {
+                    filled.push(try!(i.fill(vm, env, syn)))
+                }
+                Call(first, filled)
+            },
+            &List(ref items) => {
+                let mut filled = Vec::new();
+                for i in items {
+                    filled.push(try!(i.fill(vm, env, syn)))
+                }
+                List(filled)
+            },
+            &Dict(ref items) => {
+                let mut filled = Vec::new();
+                for &(ref k, ref v) in items {
+                    filled.push((k.clone(), try!(v.fill(vm, env, syn))))
+                }
+                Dict(filled)
+            },
+            &Quote(ref v) => Quote(try!(fill_rc(v, vm, env, syn))),
+            &At(span, ref v) => At(span, try!(fill_rc(v, vm, env, syn))),
+            &Eval(ref v) => Eval(try!(fill_rc(v, vm, env, syn))),
+            &Store(ref name, ref v) => Store(name.clone(), try!(fill_rc(v, vm, env, syn))),
+            &Func(ref params, ref body) => Func(params.clone(), try!(fill_rc(body, vm, env, syn))),
+            &Or(ref l, ref r) => Or(try!(fill_rc(l, vm, env, syn)), try!(fill_rc(r, vm, env, syn))),
+            &And(ref l, ref r) => And(try!(fill_rc(l, vm, env, syn)), try!(fill_rc(r, vm, env, syn))),
+            &Catch(ref v, ref fallback) => Catch(try!(fill_rc(v, vm, env, syn)), try!(fill_rc(fallback, vm, env, syn))),
+            _ => Val::from(self)
+        })
+    }
 }
 
 fn is_whitespace(c: char)->bool {
@@ -196,82 +677,188 @@ fn is_whitespace(c: char)->bool {
     }
 }
 
-fn parse_lambda<T>(s: &mut Iterator<Item=char>)->Result<T::ByteCode, Error>
-    where T: Vm,
-    T::Convert: From<String>,
-    String: From<T::CompileFail>,
-    Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
-    match parse_str('\'', s) {
-        // compile to VM byte code *now*, since we don't do lazy execution
-        Ok(s) => match <Result<_, _>>::from(T::Convert::from(s)) {
-            Ok(x) => Ok(x),
-            Err(err) => Err(CompileError(String::from(err)))
-        },
-        Err(err) => Err(Error::from(err))
+/// Characters to parse, counting lines and columns on the way.
+pub struct Reader<I: Iterator<Item=char>> {
+    chars: Peekable<I>,
+    pos: Pos
+}
+
+impl<I: Iterator<Item=char>> Reader<I> {
+    pub fn new(chars: I)->Reader<I> {
+        Reader { chars: chars.peekable(), pos: Pos { offset: 0, line: 1, column: 1 } }
+    }
+    /// Position of the next character.
+    pub fn pos(&self)->Pos {
+        self.pos
+    }
+    pub fn peek(&mut self)->Option<char> {
+        self.chars.peek().cloned()
+    }
+    fn error(&self, kind: ErrorKind, start: Pos)->Error {
+        Error { kind: kind, span: self.span(start) }
+    }
+    fn span(&self, start: Pos)->Span {
+        Span { start: start, end: self.pos }
     }
 }
 
-fn parse_macro(s: &mut Iterator<Item=char>)->Result<String, Error> {
-    parse_str('~', s)
+impl<I: Iterator<Item=char>> Iterator for Reader<I> {
+    type Item = char;
+    fn next(&mut self)->Option<char> {
+        let c = self.chars.next();
+        if let Some(c) = c {
+            self.pos.offset += c.len_utf8();
+            if c == '\n' {
+                self.pos.line += 1;
+                self.pos.column = 1
+            } else {
+                self.pos.column += 1
+            }
+        }
+        c
+    }
 }
 
-fn parse_list<T>(s: &mut Iterator<Item=char>)->Result<Val<T>, Error>
-    where T: Vm,
-    T::Convert: From<String>,
-    String: From<T::CompileFail>,
-    Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
-    let first = match parse::<T>(s) {
-        Ok(x) => x,
-        Err(UnexpectedChar(')')) => return Ok(Nil),
-        Err(Nothing) => return Err(Eof),
-        Err(err) => return Err(err)
-    };
-    let mut ret = Vec::new();
-    loop {
-        match parse(s) {
-            Ok(v) => ret.push(v),
-            Err(UnexpectedChar(')')) => return Ok(Call(Rc::new(first), ret)),
-            Err(Nothing) => return Err(Eof),
-            Err(err) => return Err(err)
+/// The line of `src` where `span` starts, with the span marked by carets below it.
+pub fn caret(src: &str, span: &Span)->String {
+    let start = span.start.offset;
+    let line_start = src[.. start].rfind('\n').map(|x| x + 1).unwrap_or(0);
+    let line = src[line_start ..].lines().next().unwrap_or("");
+    let mut marks = String::new();
+    for c in src[line_start .. start].chars() {
+        marks.push(if c == '\t' { '\t' } else { ' ' })
+    }
+    let width = src.get(start .. span.end.offset)
+                   .map(|x| x.chars().take_while(|&c| c != '\n').count())
+                   .unwrap_or(0);
+    for _ in 0 .. ::std::cmp::max(width, 1) {
+        marks.push('^')
+    }
+    format!("{}\n{}\n", line, marks)
+}
+
+/// Reader options for `parse_with`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Syntax {
+    /// read `'...'` as a string, as before `'` started a quasiquote
+    pub legacy_strings: bool
+}
+
+impl Syntax {
+    pub fn legacy()->Syntax {
+        Syntax { legacy_strings: true }
+    }
+}
+
+enum Tok {
+    /// string literal, with its delimiter
+    Str(char, String),
+    /// byte string literal, `b"..."`
+    Bytes(Vec<u8>),
+    Int(i32),
+    /// lambda literal, not compiled yet
+    Lambda(String),
+    Macro(String),
+    Fetch(String),
+    /// `!name=`, starting a store
+    Store(String),
+    /// `\params=`, starting a function literal
+    Params(Vec<String>),
+    /// any other single character
+    Punct(char)
+}
+
+struct Token {
+    tok: Tok,
+    span: Span
+}
+
+impl Tok {
+    // the character the token starts with
+    fn lead(&self)->char {
+        match self {
+            &Tok::Str(delim, _) => delim,
+            &Tok::Bytes(_) => 'b',
+            &Tok::Int(n) => n.to_string().chars().next().unwrap(),
+            &Tok::Lambda(_) => '`',
+            &Tok::Macro(_) => '@',
+            &Tok::Fetch(_) => '$',
+            &Tok::Store(_) => '!',
+            &Tok::Params(_) => '\\',
+            &Tok::Punct(c) => c
         }
     }
 }
 
-pub fn parse<T>(s: &mut Iterator<Item=char>)->Result<Val<T>, Error>
-    where T: Vm,
-    T::Convert: From<String>,
-    String: From<T::CompileFail>,
-    Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
-    match s.next() {
-        None => Err(Nothing),
-        Some(c) if is_whitespace(c) => parse(s),
-        Some(x) if x == '\'' || x == '"' => Ok(Str(try!(parse_str(x, s)))),
-        Some('?') => Ok(try!(parse_if(s))),
-        Some('`') => Ok(Lambda(try!(parse_lambda::<T>(s)))),
-        Some('(') => parse_list(s),
-        Some('@') => Ok(Macro(try!(parse_macro(s)))),
-        Some(x) => Err(UnexpectedChar(x)),
+// skip whitespace and `#` comments, leaving the next character to look at
+fn skip_blank<I: Iterator<Item=char>>(s: &mut Reader<I>)->Option<char> {
+    while let Some(c) = s.peek() {
+        if c == '#' {
+            while s.peek().map_or(false, |c| c != '\n') {
+                s.next();
+            }
+        } else if is_whitespace(c) {
+            s.next();
+        } else {
+            return Some(c)
+        }
     }
+    None
+}
+
+fn lex<I: Iterator<Item=char>>(s: &mut Reader<I>, syn: Syntax)->Result<Option<Token>, Error> {
+    skip_blank(s);
+    let start = s.pos();
+    let tok = match s.next() {
+        None => return Ok(None),
+        Some('\'') if syn.legacy_strings => Tok::Str('\'', try!(lex_str('\'', s))),
+        Some('"') => Tok::Str('"', try!(lex_str('"', s))),
+        Some('b') if s.peek() == Some('"') => {
+            s.next();
+            Tok::Bytes(try!(lex_bytes(s)))
+        },
+        Some('`') => Tok::Lambda(try!(lex_str('\'', s))),
+        Some('@') => Tok::Macro(try!(lex_str('~', s))),
+        Some('$') => Tok::Fetch(try!(lex_str('~', s))),
+        Some('!') => Tok::Store(try!(lex_str('=', s))),
+        Some('\\') => {
+            let params = try!(lex_str('=', s));
+            Tok::Params(params.split(is_whitespace).filter(|x| !x.is_empty()).map(|x| x.to_string()).collect())
+        },
+        Some(',') if syn.legacy_strings => return Err(s.error(UnexpectedChar(','), start)),
+        Some(c) if c.is_digit(10) || c == '-' && s.peek().map_or(false, |c| c.is_digit(10)) => {
+            let mut text = c.to_string();
+            while let Some(c) = s.peek().filter(|c| c.is_digit(10)) {
+                text.push(c);
+                s.next();
+            }
+            match text.parse() {
+                Ok(n) => Tok::Int(n),
+                Err(_) => return Err(s.error(ErrorKind::Overflow(text), start))
+            }
+        },
+        Some(c) => Tok::Punct(c)
+    };
+    Ok(Some(Token { tok: tok, span: s.span(start) }))
 }
 
-fn parse_str(delim: char, s: &mut Iterator<Item=char>)->Result<String, Error> {
+fn lex_str<I: Iterator<Item=char>>(delim: char, s: &mut Reader<I>)->Result<String, Error> {
     let mut escape = false;
     let mut ret = String::new();
-    while let Some(c) = s.next() {
+    loop {
+        let start = s.pos();
+        let c = match s.next() {
+            Some(c) => c,
+            None => return Err(s.error(Eof, start))
+        };
         if c == delim && !escape {
             return Ok(ret)
         }
         else if escape {
             escape = false;
-            ret.push(match c {
-                'r' => '\r',
-                'n' => '\n',
-                't' => '\t',
-                '\'' => '\'',
-                '"' => '"',
-                '\\' => '\\',
-                c if c == delim => delim,
-                _ => return Err(UnexpectedChar(c))
+            ret.push(match unescape(c, delim) {
+                Some(c) => c,
+                None => return Err(s.error(UnexpectedChar(c), start))
             })
         } else if c == '\\' {
             escape = true
@@ -279,19 +866,331 @@ fn parse_str(delim: char, s: &mut Iterator<Item=char>)->Result<String, Error> {
             ret.push(c)
         }
     }
-    Err(Eof)
 }
 
-fn parse_if<T>(s: &mut Iterator<Item=char>)->Result<Val<T>, Error>
+// the character an escape sequence other than `\xNN` stands for
+fn unescape(c: char, delim: char)->Option<char> {
+    match c {
+        'r' => Some('\r'),
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        '\'' | '"' | '\\' => Some(c),
+        c if c == delim => Some(delim),
+        _ => None
+    }
+}
+
+// the body of a `b"..."` literal, where `\xNN` writes any byte
+fn lex_bytes<I: Iterator<Item=char>>(s: &mut Reader<I>)->Result<Vec<u8>, Error> {
+    let mut ret = Vec::new();
+    loop {
+        let start = s.pos();
+        let c = match s.next() {
+            Some(c) => c,
+            None => return Err(s.error(Eof, start))
+        };
+        if c == '"' {
+            return Ok(ret)
+        } else if c != '\\' {
+            let mut buf = [0; 4];
+            ret.extend(c.encode_utf8(&mut buf).bytes());
+            continue
+        }
+        let start = s.pos();
+        match s.next() {
+            Some('x') => {
+                let mut hex = String::new();
+                for _ in 0 .. 2 {
+                    let here = s.pos();
+                    match s.next() {
+                        Some(c) if c.is_digit(16) => hex.push(c),
+                        Some(c) => return Err(s.error(UnexpectedChar(c), here)),
+                        None => return Err(s.error(Eof, here))
+                    }
+                }
+                ret.push(u8::from_str_radix(&hex, 16).unwrap())
+            },
+            Some(c) => match unescape(c, '"') {
+                Some(c) => ret.push(c as u8),
+                None => return Err(s.error(UnexpectedChar(c), start))
+            },
+            None => return Err(s.error(Eof, start))
+        }
+    }
+}
+
+struct Parser<'a, T: Vm + ?Sized, I: Iterator<Item=char> + 'a> {
+    s: &'a mut Reader<I>,
+    syn: Syntax,
+    ahead: Option<Token>,
+    // end of the last token taken
+    end: Pos,
+    vm: PhantomData<T>
+}
+
+impl<'a, T, I> Parser<'a, T, I> where
+    T: Vm,
+    I: Iterator<Item=char>,
+    T::Convert: From<String>,
+    String: From<T::CompileFail>,
+    Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
+    fn new(s: &'a mut Reader<I>, syn: Syntax)->Self {
+        let end = s.pos();
+        Parser { s: s, syn: syn, ahead: None, end: end, vm: PhantomData }
+    }
+    fn peek(&mut self)->Result<Option<char>, Error> {
+        if self.ahead.is_none() {
+            self.ahead = try!(lex(self.s, self.syn));
+        }
+        Ok(self.ahead.as_ref().map(|x| x.tok.lead()))
+    }
+    fn bump(&mut self)->Result<Option<Token>, Error> {
+        let next = match self.ahead.take() {
+            Some(x) => Some(x),
+            None => try!(lex(self.s, self.syn))
+        };
+        if let Some(ref x) = next {
+            self.end = x.span.end
+        }
+        Ok(next)
+    }
+    // where the next token starts, or the input ends
+    fn here(&mut self)->Result<Pos, Error> {
+        try!(self.peek());
+        Ok(match self.ahead {
+            Some(ref x) => x.span.start,
+            None => self.s.pos()
+        })
+    }
+    fn span(&self, start: Pos)->Span {
+        Span { start: start, end: self.end }
+    }
+    fn expr(&mut self)->Result<Val<T>, Error> {
+        let start = try!(self.here());
+        let mut ret = try!(self.and());
+        while try!(self.peek()) == Some('|') {
+            try!(self.bump());
+            let r = try!(self.operand(Self::and));
+            ret = At(self.span(start), Rc::new(Or(Rc::new(ret), Rc::new(r))))
+        }
+        Ok(ret)
+    }
+    fn and(&mut self)->Result<Val<T>, Error> {
+        let start = try!(self.here());
+        let mut ret = try!(self.term());
+        while try!(self.peek()) == Some('&') {
+            try!(self.bump());
+            let r = try!(self.operand(Self::term));
+            ret = At(self.span(start), Rc::new(And(Rc::new(ret), Rc::new(r))))
+        }
+        Ok(ret)
+    }
+    // the right hand side of an operator is not optional
+    fn operand(&mut self, f: fn(&mut Self)->Result<Val<T>, Error>)->Result<Val<T>, Error> {
+        match f(self) {
+            Err(Error { kind: Nothing, span }) => Err(Error { kind: Eof, span: span }),
+            x => x
+        }
+    }
+    fn term(&mut self)->Result<Val<T>, Error> {
+        let tok = match try!(self.bump()) {
+            Some(x) => x,
+            None => {
+                let here = self.s.pos();
+                return Err(self.s.error(Nothing, here))
+            }
+        };
+        let term = match tok.tok {
+            Tok::Str(_, s) => Str(s),
+            Tok::Bytes(s) => Bytes(s),
+            Tok::Int(n) => Int(n),
+            // compile to VM byte code *now*, since we don't do lazy execution
+            Tok::Lambda(code) => match <Result<_, _>>::from(T::Convert::from(code)) {
+                Ok(x) => Lambda(x),
+                Err(err) => return Err(Error { kind: CompileError(String::from(err)), span: tok.span })
+            },
+            Tok::Macro(name) => Macro(name),
+            Tok::Fetch(name) => Fetch(name),
+            Tok::Store(name) => Store(name, Rc::new(try!(self.quoted()))),
+            Tok::Params(params) => Func(Rc::new(params), Rc::new(try!(self.quoted()))),
+            // a template quotes a single term, holes belong to the innermost template
+            Tok::Punct('\'') => {
+                let template = try!(self.operand(Self::term));
+                if template.has_holes() {
+                    Quasi(Rc::new(template))
+                } else {
+                    Quote(Rc::new(template))
+                }
+            },
+            Tok::Punct(',') => Unquote(Rc::new(try!(self.operand(Self::term)))),
+            Tok::Punct('?') => {
+                let (p, t, f) = (try!(self.expr()), try!(self.expr()), try!(self.expr()));
+                If(Rc::new(p), Rc::new(t), Rc::new(f))
+            },
+            Tok::Punct('%') => {
+                let (v, fallback) = (try!(self.expr()), try!(self.expr()));
+                Catch(Rc::new(v), Rc::new(fallback))
+            },
+            Tok::Punct('(') => try!(self.list()),
+            Tok::Punct('[') => {
+                let mut items = Vec::new();
+                while try!(self.peek()) != Some(']') {
+                    items.push(try!(self.operand(Self::expr)))
+                }
+                try!(self.bump());
+                List(items)
+            },
+            Tok::Punct('{') => {
+                let mut items: Vec<(String, Val<T>)> = Vec::new();
+                loop {
+                    let key = match try!(self.bump()) {
+                        Some(Token { tok: Tok::Punct('}'), .. }) => break,
+                        Some(Token { tok: Tok::Str(_, key), span }) => if items.iter().any(|x| x.0 == key) {
+                            return Err(Error { kind: ErrorKind::Duplicate(key), span: span })
+                        } else {
+                            key
+                        },
+                        Some(x) => return Err(Error { kind: UnexpectedChar(x.tok.lead()), span: x.span }),
+                        None => {
+                            let here = self.s.pos();
+                            return Err(self.s.error(Eof, here))
+                        }
+                    };
+                    items.push((key, try!(self.operand(Self::expr))))
+                }
+                Dict(items)
+            },
+            Tok::Punct('^') => Quote(Rc::new(try!(self.quoted()))),
+            Tok::Punct('*') => Eval(Rc::new(try!(self.quoted()))),
+            Tok::Punct(c) => return Err(Error { kind: UnexpectedChar(c), span: tok.span })
+        };
+        Ok(At(self.span(tok.span.start), Rc::new(term)))
+    }
+    fn list(&mut self)->Result<Val<T>, Error> {
+        let mut ret = Vec::new();
+        loop {
+            match try!(self.peek()) {
+                Some(')') => {
+                    try!(self.bump());
+                    break
+                },
+                Some(_) => ret.push(try!(self.expr())),
+                None => {
+                    let here = self.s.pos();
+                    return Err(self.s.error(Eof, here))
+                }
+            }
+        }
+        if ret.is_empty() {
+            Ok(Nil)
+        } else {
+            let first = ret.remove(0);
+            Ok(Call(Rc::new(first), ret))
+        }
+    }
+    // an expression closed by `~`
+    fn quoted(&mut self)->Result<Val<T>, Error> {
+        let quoted = try!(self.operand(Self::expr));
+        match try!(self.bump()) {
+            Some(Token { tok: Tok::Punct('~'), .. }) => Ok(quoted),
+            Some(x) => Err(Error { kind: UnexpectedChar(x.tok.lead()), span: x.span }),
+            None => {
+                let here = self.s.pos();
+                Err(self.s.error(Eof, here))
+            }
+        }
+    }
+    // anything left over is an error
+    fn finish(&mut self)->Result<(), Error> {
+        match try!(self.bump()) {
+            Some(x) => Err(Error { kind: UnexpectedChar(x.tok.lead()), span: x.span }),
+            None => Ok(())
+        }
+    }
+}
+
+/// Parse one expression, the whole input: a term, or terms joined by `|` and `&`,
+/// where `&` binds tighter and both associate to the left.
+pub fn parse<T, I>(s: &mut Reader<I>)->Result<Val<T>, Error>
+    where T: Vm,
+    I: Iterator<Item=char>,
+    T::Convert: From<String>,
+    String: From<T::CompileFail>,
+    Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
+    parse_with(s, Syntax::default())
+}
+
+pub fn parse_with<T, I>(s: &mut Reader<I>, syn: Syntax)->Result<Val<T>, Error>
+    where T: Vm,
+    I: Iterator<Item=char>,
+    T::Convert: From<String>,
+    String: From<T::CompileFail>,
+    Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
+    let mut p = Parser::new(s, syn);
+    let ret = try!(p.expr());
+    try!(p.finish());
+    Ok(ret)
+}
+
+/// Parse a sequence of top-level expressions, up to the end of the input.
+pub fn parse_all<T, I>(s: &mut Reader<I>, syn: Syntax)->Result<Vec<Val<T>>, Error>
     where T: Vm,
+    I: Iterator<Item=char>,
     T::Convert: From<String>,
     String: From<T::CompileFail>,
     Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
-    let (p, t, f) = (try!(parse(s)), try!(parse(s)), try!(parse(s)));
-    Ok(If(Rc::new(p), Rc::new(t), Rc::new(f)))
+    let mut p = Parser::new(s, syn);
+    let mut ret = Vec::new();
+    while try!(p.peek()).is_some() {
+        ret.push(try!(p.expr()))
+    }
+    Ok(ret)
+}
+
+/// Why `eval_all` stopped before the end of a script.
+pub enum ScriptError {
+    Parse(Error),
+    Eval(String)
+}
+
+impl Debug for ScriptError {
+    fn fmt(&self, f: &mut Formatter)->Result<(), FmtError> {
+        match self {
+            &ScriptError::Parse(ref err) => write!(f, "{:?}", err),
+            &ScriptError::Eval(ref err) => write!(f, "{}", err)
+        }
+    }
+}
+
+/// Evaluate the top-level expressions of `src` in order, handing each result to `each`.
+/// Nothing runs unless the whole script parses, and a macro asking to quit ends it early.
+pub fn eval_all<T>(vm: &mut T, src: &str, syn: Syntax, each: &mut FnMut(Val<T>))->Result<(), ScriptError> where
+    T: Vm,
+    T::Convert: From<String>,
+    String: From<T::CompileFail>,
+    Result<T::ByteCode, T::CompileFail>: From<T::Convert>,
+    T::ByteCode: Display + Clone {
+    let exprs = try!(parse_all::<T, _>(&mut Reader::new(src.chars()), syn).map_err(ScriptError::Parse));
+    for x in exprs {
+        match x.calc_with(vm, syn) {
+            Calc::Ok(v) => each(v),
+            Calc::Err(err) => return Err(ScriptError::Eval(err)),
+            Calc::Quit => break
+        }
+    }
+    Ok(())
 }
 
 pub fn repl<T>(vm: &mut T) where
+    T: Vm,
+    T::Convert: From<String>,
+    String: From<T::CompileFail>,
+    Result<T::ByteCode, T::CompileFail>: From<T::Convert>,
+    T::ByteCode: Display + Clone {
+    repl_with(vm, Syntax::default())
+}
+
+pub fn repl_with<T>(vm: &mut T, syn: Syntax) where
     T: Vm,
     T::Convert: From<String>,
     String: From<T::CompileFail>,
@@ -310,35 +1209,39 @@ pub fn repl<T>(vm: &mut T) where
             stdout().write(b">").unwrap();
             stdout().flush().unwrap()
         }
-        stdin().read_line(&mut line).unwrap();
-        let mut char_reader = line.chars();
-        match parse::<T>(&mut char_reader) {
-            Err(Nothing) => { /* nothing parsed, fine, just go to next loop */ },
-            Ok(ref x) => {
-                let unexpected = char_reader.find(|&x| !is_whitespace(x));
-                if let Some(c) = unexpected {
-                    println!("error: unexpected `{}`", c.escape_default().collect::<String>());
-                } else {
-                    println!("{}", match x.calc(vm) {
-                        Calc::Ok(x) => x.to_string(),
-                        Calc::Err(err) => err,
-                        Calc::Quit => return
-                    })
-                }
+        // end of input, as with ctrl-d or a closed pipe
+        match stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                println!("");
+                return
+            },
+            Ok(_) => ()
+        }
+        let mut char_reader = Reader::new(line.chars());
+        match parse_all::<T, _>(&mut char_reader, syn) {
+            Ok(exprs) => for x in exprs {
+                println!("{}", match x.calc_with(vm, syn) {
+                    Calc::Ok(x) => x.to_string(),
+                    Calc::Err(err) => err,
+                    Calc::Quit => return
+                })
             },
-            Err(Eof) => {
+            Err(Error { kind: Eof, .. }) => {
                 // missing closing delim here, we copy the buffer to wait for incoming characters
                 continue_ = true
             },
-            Err(UnexpectedChar(c)) => {
-                println!("failed to parse expression: unexpected `{}`",
-                         c.escape_default().collect::<String>());
-                stdout().flush().unwrap();
-                stdout().write(b"\n").unwrap();
-                stdout().flush().unwrap()
+            Err(Error { kind: UnexpectedChar(c), span }) => {
+                println!("failed to parse expression: unexpected `{}` at {}",
+                         c.escape_default().collect::<String>(), span.start);
+                print!("{}", caret(&line, &span))
+            },
+            Err(Error { kind: CompileError(err), span }) => {
+                println!("illegal lambda literal at {}: {}", span.start, err);
+                print!("{}", caret(&line, &span))
             },
-            Err(CompileError(err)) => {
-                println!("illegal lambda literal: {}", err);
+            Err(Error { kind, span }) => {
+                println!("failed to parse expression: {:?} at {}", kind, span.start);
+                print!("{}", caret(&line, &span))
             }
         }
     }
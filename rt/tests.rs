@@ -1,14 +1,338 @@
+use std::rc::Rc;
+use super::Val;
+
+// the mocks at the bottom all keep lambdas as plain strings
+fn parse<T>(s: &str)->Result<Val<T>, super::Error> where T: super::Vm<ByteCode=String, CompileFail=String, Convert=Convert> {
+    super::parse(&mut super::Reader::new(s.chars()))
+}
+
+fn eval<T>(vm: &mut T, s: &str)->super::CalcResult<Val<T>> where T: super::Vm<ByteCode=String, CompileFail=String, Convert=Convert> {
+    parse(s).unwrap().calc(vm)
+}
+
 #[test]
 fn test_basic_lambda() {
-    assert_eq!(super::parse_lambda::<Vm>(&mut "'".chars()).unwrap(), "")
+    let lambda = parse::<Vm>("`'").unwrap();
+    assert_eq!(lambda.calc(&mut Vm).unwrap().to_string(), "`'")
 }
 
 #[test]
 fn test_branches() {
-    let vm = super::parse(&mut " ? () 'a' 'b' ".chars()).unwrap();
+    let vm = super::parse_with(&mut super::Reader::new(" ? () 'a' 'b' ".chars()), super::Syntax::legacy()).unwrap();
     assert_eq!(vm.calc(&mut Vm).unwrap().to_string(), r##"b"##)
 }
 
+#[test]
+fn test_quote() {
+    let quote = parse::<Vm>(r#"^ (@f~ ? () "a" "b") ~"#).unwrap();
+    assert_eq!(quote.kind(), "quote");
+    let value = quote.calc(&mut Vm).unwrap();
+    assert_eq!(value.to_string(), "^(@f~ [ <if expression> ])~");
+    let nested = parse::<Vm>("^^@f~~~").unwrap();
+    assert_eq!(nested.to_string(), "^^@f~~~");
+    assert!(parse::<Vm>(r#"^"a" "b"~"#).is_err())
+}
+
+#[test]
+fn test_eval() {
+    assert_eq!(eval(&mut Vm, r#"*^? () "a" "b"~~"#).unwrap().to_string(), "b");
+    assert_eq!(eval(&mut Vm, r#"*"? \"x\" `+' \"y\""~"#).unwrap().to_string(), "`+'");
+    assert_eq!(eval(&mut Vm, r#"*^^"a"~~~"#).unwrap().to_string(), "^a~");
+    assert_eq!(eval(&mut Vm, r#"**^^"a"~~~~"#).unwrap().to_string(), "a");
+    for code in &[ r#"*"? ()"~"#, r#"*"plain"~"#, r#"*"() ()"~"# ] {
+        match eval(&mut Vm, code) {
+            super::Calc::Err(_) => (),
+            _ => panic!("evaluating {} should fail", code)
+        }
+    }
+    // evaluated strings are read with the syntax of the code evaluating them
+    let mut strings = Vec::new();
+    super::eval_all(&mut Vm, r#"*"'a'"~"#, super::Syntax::legacy(), &mut |v| strings.push(v.to_string())).unwrap();
+    assert_eq!(strings, vec![ "a" ]);
+    assert!(super::eval_all(&mut Vm, r#"*"'a'"~"#, super::Syntax::default(), &mut |_| ()).is_err())
+}
+
+#[test]
+fn test_variables() {
+    let mut vm = Store::default();
+    assert_eq!(eval(&mut vm, r#"!x=? () "a" "b"~"#).unwrap().to_string(), "b");
+    assert_eq!(eval(&mut vm, "$x~").unwrap().to_string(), "b");
+    assert_eq!(eval(&mut vm, "!y=^$x~~~").unwrap().to_string(), "^$x~~");
+    assert_eq!(eval(&mut vm, "*$y~~").unwrap().to_string(), "b");
+    match eval(&mut vm, "$z~") {
+        super::Calc::Err(_) => (),
+        _ => panic!("fetching an undefined variable should fail")
+    }
+}
+
+#[test]
+fn test_logic() {
+    assert_eq!(eval(&mut Vm, r#""a" | "b""#).unwrap().to_string(), "a");
+    assert_eq!(eval(&mut Vm, r#""" | () | "b""#).unwrap().to_string(), "b");
+    assert_eq!(eval(&mut Vm, r#""a" & "b""#).unwrap().to_string(), "b");
+    assert_eq!(eval(&mut Vm, r#"() & "b""#).unwrap().to_string(), "nil");
+    // `&` binds tighter, and the right side is never touched when the left decides
+    assert_eq!(eval(&mut Vm, r#""a" | () & @undefined~"#).unwrap().to_string(), "a");
+    assert_eq!(eval(&mut Vm, r#"() & @undefined~ | "c""#).unwrap().to_string(), "c");
+    assert_eq!(eval(&mut Vm, r#"? "x" & "" "then" "else""#).unwrap().to_string(), "else");
+    assert!(parse::<Vm>(r#"("a" |)"#).is_err())
+}
+
+#[test]
+fn test_closures() {
+    let mut vm = Store::default();
+    assert_eq!(eval(&mut vm, "\\x=$x~~").unwrap().to_string(), "\\x=$x~~");
+    assert_eq!(eval(&mut vm, r#"(\x y=$y~ | $x~~ "" "b")"#).unwrap().to_string(), "b");
+    // the inner function keeps seeing `x` after the outer call returned
+    assert_eq!(eval(&mut vm, "!k=\\x=\\y=$x~~~~").unwrap().kind(), "closure");
+    assert_eq!(eval(&mut vm, r#"(($k~ "a") "b")"#).unwrap().to_string(), "a");
+    assert_eq!(eval(&mut vm, r#"!x="global"~"#).unwrap().to_string(), "global");
+    assert_eq!(eval(&mut vm, "(\\=$x~~)").unwrap().to_string(), "global");
+    match eval(&mut vm, "($k~)") {
+        super::Calc::Err(_) => (),
+        _ => panic!("calling with too few arguments should fail")
+    }
+}
+
+#[test]
+fn test_quasiquote() {
+    let mut vm = Store::default();
+    // a template without holes is a plain quote
+    assert_eq!(eval(&mut vm, "'(@f~ $x~)").unwrap().to_string(), "^(@f~ [ $x~ ])~");
+    eval(&mut vm, r#"!x="a"~"#).unwrap();
+    assert_eq!(eval(&mut vm, r#"'(@f~ ,$x~ ,"b")"#).unwrap().to_string(), "^(@f~ [ a b ])~");
+    assert_eq!(eval(&mut vm, r#"*'? ,$x~ "then" "else"~"#).unwrap().to_string(), "then");
+    // holes of a nested template stay until that one is evaluated
+    assert_eq!(eval(&mut vm, "''(@f~ ,$x~)").unwrap().to_string(), "^'(@f~ [ ,$x~ ])~");
+    assert_eq!(eval(&mut vm, "*''(@f~ ,$x~)~").unwrap().to_string(), "^(@f~ [ a ])~");
+    match eval(&mut vm, ",$x~") {
+        super::Calc::Err(_) => (),
+        _ => panic!("unquoting outside of a template should fail")
+    }
+    let legacy = |s: &str| super::parse_with::<Vm, _>(&mut super::Reader::new(s.chars()), super::Syntax::legacy());
+    assert_eq!(legacy("'a'").unwrap().kind(), "str");
+    assert!(legacy(",'a'").is_err())
+}
+
+#[test]
+fn test_strategy() {
+    assert_eq!(eval(&mut Echo, r#"(`eager' (`eager' "a") "b")"#).unwrap().to_string(), "a b");
+    assert_eq!(eval(&mut Echo, r#"(`lazy' (@undefined~ "a") "b")"#).unwrap().to_string(), "(@undefined~ [ a ]) b");
+    assert_eq!(eval(&mut Echo, "(`lazy' $x~)").unwrap().to_string(), "$x~");
+    match eval(&mut Echo, "(`eager' $x~)") {
+        super::Calc::Err(_) => (),
+        _ => panic!("an undefined argument should fail before the call")
+    }
+}
+
+#[test]
+fn test_positions() {
+    let err = |s: &str| match parse::<Vm>(s) {
+        Err(err) => err,
+        Ok(v) => panic!("parsing {:?} should fail, found {}", s, v)
+    };
+    let e = err("(@f~\n  \"a\" ;)");
+    assert_eq!((e.span.start.line, e.span.start.column, e.span.start.offset), (2, 7, 11));
+    assert_eq!(super::caret("(@f~\n  \"a\" ;)", &e.span), "  \"a\" ;)\n      ^\n");
+    let e = err("(\"\\q\")");
+    assert_eq!((e.span.start.column, e.span.end.column), (4, 5));
+    match err("(\"a\"").kind {
+        super::ErrorKind::Eof => (),
+        _ => panic!("an unclosed list should ask for more input")
+    }
+    // parsed expressions remember where they came from
+    match parse::<Vm>("  @f~ | @g~").unwrap() {
+        super::Val::At(span, _) => assert_eq!((span.start.column, span.end.column), (3, 12)),
+        v => panic!("expression with a span expected, found {}", v)
+    }
+}
+
+#[test]
+fn test_parse_all() {
+    let all = |s: &str| super::parse_all::<Vm, _>(&mut super::Reader::new(s.chars()), super::Syntax::default());
+    let src = "# two expressions\n\"a\" | \"b\" # the first\n(`+' \"c\")\n";
+    let exprs = all(src).unwrap();
+    assert_eq!(exprs.iter().map(|x| x.kind()).collect::<Vec<_>>(), vec![ "or", "call" ]);
+    assert!(all("  # nothing but a comment").unwrap().is_empty());
+    match all("\"a\" (") {
+        Err(super::Error { kind: super::ErrorKind::Eof, .. }) => (),
+        _ => panic!("an unclosed list should ask for more input")
+    }
+    // a single expression is the whole input
+    assert!(parse::<Vm>("\"a\" \"b\"").is_err());
+    let blank = " ".repeat(1 << 20) + "\"a\"";
+    assert_eq!(parse::<Vm>(&blank).unwrap().to_string(), "a")
+}
+
+#[test]
+fn test_eval_all() {
+    let mut vm = Store::default();
+    let mut results = Vec::new();
+    let src = "!x=\"a\"~ # keep it\n$x~ | \"b\"\n$y~\n\"never\"";
+    match super::eval_all(&mut vm, src, super::Syntax::default(), &mut |v| results.push(v.to_string())) {
+        Err(super::ScriptError::Eval(_)) => (),
+        other => panic!("the undefined variable should stop the script, found {:?}", other)
+    }
+    assert_eq!(results, vec![ "a", "a" ]);
+    match super::eval_all(&mut vm, "!z=\"c\"~ (", super::Syntax::default(), &mut |_| ()) {
+        Err(super::ScriptError::Parse(_)) => assert!(vm.0.iter().all(|x| x.0 != "z")),
+        other => panic!("an unclosed list should fail to parse, found {:?}", other)
+    }
+}
+
+#[test]
+fn test_to_source() {
+    let source = |s: &str| parse::<Vm>(s).unwrap().to_source();
+    assert_eq!(source(r#"?()  "a\n"'"b""#), r#"? () "a\n" ^"b"~"#);
+    assert_eq!(source(r#"(@f~ `+\''   $x~ | "" & "y")"#), r#"(@f~ `+\'' $x~ | "" & "y")"#);
+    assert_eq!(source(r#"!x=\a b='(,$a~ $b~)~~"#), r#"!x=\a b='(,$a~ $b~)~~"#);
+    // not something `parse` builds, but it still evaluates the same
+    let or = Val::<Vm>::Or(Rc::new(Val::Str("a".to_string())), Rc::new(Val::Nil));
+    let and = Val::And(Rc::new(or), Rc::new(Val::Nil));
+    assert_eq!(and.to_source(), r#"*^"a" | ()~~ & ()"#)
+}
+
+#[test]
+fn test_source_round_trip() {
+    let mut rand = Rand(0x2545F4914F6CDD1D);
+    for _ in 0 .. 2000 {
+        let v = gen(&mut rand, 4, super::IN_EXPR);
+        let src = v.to_source();
+        match parse::<Vm>(&src) {
+            Ok(ref x) if *x == v => assert_eq!(x.to_source(), src),
+            Ok(x) => panic!("{} read back as {}", src, x.to_source()),
+            Err(err) => panic!("{} failed to parse: {:?}", src, err)
+        }
+    }
+}
+
+// xorshift, so the cases are the same on every run
+struct Rand(u64);
+
+impl Rand {
+    fn below(&mut self, n: usize)->usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+    fn text(&mut self, chars: &[char])->String {
+        (0 .. self.below(4)).map(|_| chars[self.below(chars.len())]).collect()
+    }
+}
+
+// random trees of the shapes `parse` builds, for the context given as in `Val::source`
+fn gen(r: &mut Rand, depth: usize, ctx: usize)->Val<Vm> {
+    const TEXT: &[char] = &[ 'a', ' ', '"', '\'', '\\', '~', '=', '#', '\n', '\t', 'é', '中' ];
+    const NAME: &[char] = &[ 'x', 'y', 'z' ];
+    let sub = |r: &mut Rand, ctx| Rc::new(gen(r, depth - 1, ctx));
+    loop {
+        return match r.below(if depth == 0 { 6 } else { 20 }) {
+            0 => Val::Str(r.text(TEXT)),
+            1 => Val::Nil,
+            2 => Val::Macro(r.text(TEXT)),
+            3 => Val::Fetch(r.text(TEXT)),
+            4 => Val::Lambda(r.text(TEXT)),
+            5 => Val::Int(r.below(usize::max_value()) as u32 as i32),
+            6 => Val::If(sub(r, super::IN_EXPR), sub(r, super::IN_EXPR), sub(r, super::IN_EXPR)),
+            7 => {
+                let first = sub(r, super::IN_EXPR);
+                Val::Call(first, (0 .. r.below(3)).map(|_| gen(r, depth - 1, super::IN_EXPR)).collect())
+            },
+            8 => Val::Quote(sub(r, super::IN_EXPR)),
+            9 => Val::Eval(sub(r, super::IN_EXPR)),
+            10 => Val::Store(r.text(TEXT), sub(r, super::IN_EXPR)),
+            11 => {
+                let params = (0 .. r.below(3)).map(|_| r.text(NAME)).filter(|x| !x.is_empty()).collect();
+                Val::Func(Rc::new(params), sub(r, super::IN_EXPR))
+            },
+            // a template needs a hole of its own to stay one
+            12 => {
+                let hole = Val::Unquote(sub(r, super::IN_TERM));
+                Val::Quasi(Rc::new(Val::Call(sub(r, super::IN_EXPR), vec![ hole ])))
+            },
+            13 => Val::Unquote(sub(r, super::IN_TERM)),
+            14 if ctx == super::IN_EXPR => Val::Or(closed(r, depth - 1, super::IN_EXPR), sub(r, super::IN_AND)),
+            15 if ctx <= super::IN_AND => Val::And(closed(r, depth - 1, super::IN_AND), sub(r, super::IN_TERM)),
+            16 => Val::List((0 .. r.below(3)).map(|_| gen(r, depth - 1, super::IN_EXPR)).collect()),
+            17 => {
+                let mut items: Vec<(String, Val<Vm>)> = Vec::new();
+                for _ in 0 .. r.below(3) {
+                    let key = r.text(TEXT);
+                    if items.iter().all(|x| x.0 != key) {
+                        items.push((key, gen(r, depth - 1, super::IN_EXPR)))
+                    }
+                }
+                Val::Dict(items)
+            },
+            18 => Val::Bytes((0 .. r.below(4)).map(|_| r.below(256) as u8).collect()),
+            19 => Val::Catch(sub(r, super::IN_EXPR), sub(r, super::IN_EXPR)),
+            _ => continue
+        }
+    }
+}
+
+fn closed(r: &mut Rand, depth: usize, ctx: usize)->Rc<Val<Vm>> {
+    loop {
+        let v = gen(r, depth, ctx);
+        if !v.open_ended() {
+            return Rc::new(v)
+        }
+    }
+}
+
+#[test]
+fn test_int() {
+    let call = parse::<Vm>("(`+'  42 -7\t0)").unwrap();
+    assert_eq!(call.to_source(), "(`+' 42 -7 0)");
+    assert_eq!(eval(&mut Vm, "-2147483648").unwrap().kind(), "int");
+    assert!(eval(&mut Vm, "0 | 1").unwrap() == Val::Int(0));
+    for code in &[ "2147483648", "- 1", "1-" ] {
+        assert!(parse::<Vm>(code).is_err(), "{} should not parse", code)
+    }
+}
+
+#[test]
+fn test_collections() {
+    let mut vm = Store::default();
+    eval(&mut vm, r#"!x="a"~"#).unwrap();
+    assert_eq!(eval(&mut vm, r#"[$x~ [] "b" | () 1]"#).unwrap().to_string(), "[a, [], b, 1]");
+    assert_eq!(eval(&mut vm, r#"{"k" $x~ "" {"n" [()]}}"#).unwrap().to_string(), "{k: a, : {n: [nil]}}");
+    assert_eq!(eval(&mut vm, r#"'[,$x~ $x~]"#).unwrap().to_source(), r#"^["a" $x~]~"#);
+    for code in &[ r#"{"k" 1 "k" 2}"#, "{1 2}", r#"{"k"}"#, "[1" ] {
+        assert!(parse::<Vm>(code).is_err(), "{} should not parse", code)
+    }
+}
+
+#[test]
+fn test_bytes() {
+    let bytes = parse::<Vm>(r#"b"A\x00\xfF\n\"中""#).unwrap();
+    assert!(bytes == Val::Bytes(vec![ b'A', 0, 0xFF, b'\n', b'"', 0xE4, 0xB8, 0xAD ]));
+    assert_eq!(bytes.to_string(), r#"A\x00\xFF\n"\xE4\xB8\xAD"#);
+    assert_eq!(super::pretty(b"0x00\x00\\"), r#"0x00\x00\\"#);
+    assert_eq!(bytes.to_source(), r#"b"A\x00\xFF\n\"\xE4\xB8\xAD""#);
+    assert!(!Val::<Vm>::Bytes(Vec::new()).is_true());
+    for code in &[ r#"b"\x4""#, r#"b"\xg0""#, r#"b"\q""#, r#"b"ab"# ] {
+        assert!(parse::<Vm>(code).is_err(), "{} should not parse", code)
+    }
+}
+
+#[test]
+fn test_catch() {
+    let mut vm = Store::default();
+    assert_eq!(eval(&mut vm, r#"% "ok" $error~"#).unwrap().to_string(), "ok");
+    assert_eq!(eval(&mut vm, r#"% $x~ $error~"#).unwrap().to_string(), "variable `x` not defined");
+    assert_eq!(eval(&mut vm, r#"% % $x~ $y~ [$error~]"#).unwrap().to_string(), "[variable `y` not defined]");
+    // errors in the fallback are not caught, and the message is only bound inside it
+    for &(code, msg) in &[ (r#"% $x~ $y~"#, "variable `y` not defined"),
+                           (r#"[% $x~ () $error~]"#, "variable `error` not defined") ] {
+        match eval(&mut vm, code) {
+            super::Calc::Err(err) => assert_eq!(err, msg),
+            _ => panic!("{} should fail", code)
+        }
+    }
+}
+
 struct Vm;
 impl super::Vm for Vm {
     type ByteCode = String;
@@ -16,6 +340,38 @@ impl super::Vm for Vm {
     type Convert = Convert;
 }
 
+#[derive(Default)]
+struct Store(Vec<(String, super::Val<Store>)>);
+impl super::Vm for Store {
+    type ByteCode = String;
+    type CompileFail = String;
+    type Convert = Convert;
+    fn store(&mut self, name: &str, v: super::Val<Self>) {
+        self.0.push((name.to_string(), v))
+    }
+    fn fetch(&mut self, name: &str)->Option<super::Val<Self>> {
+        self.0.iter().rev().find(|x| x.0 == name).map(|x| super::Val::from(&x.1))
+    }
+}
+
+// returns its arguments, call by name for the lambda `lazy`
+struct Echo;
+impl super::Vm for Echo {
+    type ByteCode = String;
+    type CompileFail = String;
+    type Convert = Convert;
+    fn run(&mut self, _: &String, args: &Vec<super::Val<Self>>)->Result<super::Val<Self>, String> {
+        Ok(super::Val::Str(args.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")))
+    }
+    fn strategy(&mut self, code: &String)->super::Strategy {
+        if code == "lazy" {
+            super::Strategy::ByName
+        } else {
+            super::Strategy::Eager
+        }
+    }
+}
+
 pub struct Convert(String);
 
 impl From<String> for Convert {
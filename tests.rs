@@ -0,0 +1,199 @@
+use std::rc::Rc;
+use super::BfVm;
+use utils::{rt2bencode, bencode2rt};
+
+type Val = ::rt::Val<BfVm>;
+
+fn round_trip(v: Val)->Val {
+    let bytes = rt2bencode(v);
+    bencode2rt(::bencode::parse(&mut bytes.iter().cloned()).unwrap()).unwrap()
+}
+
+#[test]
+fn test_lambda_argument() {
+    let lambda = Val::Lambda(::bf::Vm::add_one());
+    assert_eq!(rt2bencode(Val::from(&lambda)), b"d6:lambda15:,.>,.>,.>,+.>,.e".to_vec());
+    assert_eq!(round_trip(lambda).to_string(), "`,.>,.>,.>,+.>,.'")
+}
+
+#[test]
+fn test_nested_values() {
+    let call = Val::Call(Rc::new(Val::Macro("add_one".to_string())), vec![ Val::Str("a".to_string()) ]);
+    let branch = Val::If(Rc::new(Val::Str("p".to_string())),
+                         Rc::new(call),
+                         Rc::new(Val::Lambda(::bf::Vm::print(b"1:A"))));
+    match round_trip(branch) {
+        Val::If(p, t, f) => {
+            assert_eq!(p.to_string(), "p");
+            assert_eq!(t.to_string(), "(@add_one~ [ a ])");
+            assert_eq!(f.kind(), "lambda")
+        },
+        v => panic!("if expression expected, found {}", v)
+    }
+}
+
+#[test]
+fn test_func_argument() {
+    let mut s = ::rt::Reader::new("\\x y=$y~ | $x~~".chars());
+    let func = ::rt::parse::<BfVm, _>(&mut s).unwrap();
+    assert_eq!(round_trip(func).to_string(), "\\x y=$y~ | $x~~")
+}
+
+#[test]
+fn test_lambda_source() {
+    let mut s = ::rt::Reader::new("(`#pbrain +(-):' ^`,.'~)".chars());
+    let call = ::rt::parse::<BfVm, _>(&mut s).unwrap();
+    let src = call.to_source();
+    assert_eq!(src, "(`#pbrain +(-):' ^`,.'~)");
+    assert!(::rt::parse::<BfVm, _>(&mut ::rt::Reader::new(src.chars())).unwrap() == call)
+}
+
+#[test]
+fn test_int_argument() {
+    assert_eq!(rt2bencode(Val::Int(-12)), b"i-12e".to_vec());
+    assert!(round_trip(Val::Int(42)) == Val::Int(42))
+}
+
+#[test]
+fn test_collection_argument() {
+    let list = Val::List(vec![ Val::Int(1), Val::Str("a".to_string()), Val::List(Vec::new()) ]);
+    assert_eq!(rt2bencode(Val::from(&list)), b"li1e1:alee".to_vec());
+    assert!(round_trip(Val::from(&list)) == list);
+    let dict = Val::Dict(vec![ ("b".to_string(), Val::Int(2)), ("a".to_string(), Val::Nil) ]);
+    assert_eq!(rt2bencode(dict), b"d1:a0:1:bi2ee".to_vec());
+    // would read back as a string if sent along untagged
+    let tagged = Val::Dict(vec![ ("str".to_string(), Val::Str("x".to_string())) ]);
+    assert_eq!(rt2bencode(Val::from(&tagged)), b"d4:dict10:d3:str1:xee".to_vec());
+    assert!(round_trip(Val::from(&tagged)) == tagged)
+}
+
+#[test]
+fn test_collection_results() {
+    let results = |s: &[u8]| bencode2rt::<BfVm>(::bencode::parse(&mut s.iter().cloned()).unwrap());
+    assert_eq!(results(b"llee").unwrap().to_string(), "[]");
+    assert_eq!(results(b"ll1:aee").unwrap().to_string(), "[a]");
+    assert!(results(b"d2:\xFF\x00i1ee").is_err());
+    assert_eq!(results(b"le").unwrap().kind(), "nil");
+    assert_eq!(results(b"l1:ae").unwrap().to_string(), "a");
+    assert_eq!(results(b"l1:ai2ee").unwrap().to_string(), "[a, 2]");
+    assert_eq!(results(b"ld1:ki1eee").unwrap().to_string(), "{k: 1}")
+}
+
+#[test]
+fn test_bytes_argument() {
+    let binary = Val::Bytes(vec![ 0xFF, 0 ]);
+    assert_eq!(rt2bencode(Val::from(&binary)), b"2:\xFF\x00".to_vec());
+    assert!(round_trip(binary) == Val::Bytes(vec![ 0xFF, 0 ]));
+    let text = Val::Bytes(b"hi".to_vec());
+    assert_eq!(rt2bencode(Val::from(&text)), b"d5:bytes2:hie".to_vec());
+    assert!(round_trip(text) == Val::Bytes(b"hi".to_vec()))
+}
+
+#[test]
+fn test_returned_error() {
+    use rt::Vm;
+    let mut vm = BfVm::default();
+    let fail = ::bf::Vm::print(b"d5:error9:bad inpute");
+    assert_eq!(vm.run(&fail, &Vec::new()).unwrap_err(), "bad input");
+    // the usual result list, and errors nested in results fail all the same
+    let fail = ::bf::Vm::print(b"ld5:error3:badee");
+    assert_eq!(vm.run(&fail, &Vec::new()).unwrap_err(), "bad");
+    let fail = ::bf::Vm::print(b"l1:ald5:error3:badeee");
+    assert_eq!(vm.run(&fail, &Vec::new()).unwrap_err(), "bad");
+    // a dictionary sent along is tagged, so echoing it back does not fail
+    let dict = Val::Dict(vec![ ("error".to_string(), Val::Str("x".to_string())) ]);
+    assert_eq!(rt2bencode(Val::from(&dict)), b"d4:dict12:d5:error1:xee".to_vec());
+    assert!(round_trip(Val::from(&dict)) == dict)
+}
+
+#[test]
+fn test_extension_lambda_argument() {
+    let code = match ::bf::Convert::from("#host %") {
+        ::bf::Convert::Ok(code) => code,
+        ::bf::Convert::Err(err) => panic!("{}", err)
+    };
+    let lambda = Val::Lambda(code);
+    assert_eq!(rt2bencode(Val::from(&lambda)), b"d6:lambda7:#host %e".to_vec());
+    assert!(round_trip(Val::from(&lambda)) == lambda)
+}
+
+#[test]
+fn test_lazy_macro() {
+    use rt::{Vm, Strategy};
+    let mut vm = BfVm::default();
+    for _ in 0 .. 2 {
+        assert!(match vm.macro_expand("lazy add_one") { ::rt::MacroResult::Continue => true, _ => false })
+    }
+    assert_eq!(vm.lazy.len(), 1);
+    assert_eq!(vm.strategy(&::bf::Vm::add_one()), Strategy::ByName);
+    assert_eq!(vm.strategy(&::bf::Vm::print(b"1:A")), Strategy::Eager)
+}
+
+#[test]
+fn test_host_write_back() {
+    use rt::Vm;
+    use bf::Host;
+    let mut vm = BfVm::default();
+    vm.store("short", Val::Str("abc".to_string()));
+    vm.store("long", Val::Str("x".repeat(::bf::HOST_WINDOW)));
+    let mut window = [0; ::bf::HOST_WINDOW];
+    window[0] = super::HOST_FETCH;
+    window[1 .. 6].copy_from_slice(b"short");
+    assert!(vm.call(&mut window).is_ok());
+    assert_eq!(&window[1 .. 5], b"abc\0");
+    window[1 .. 5].copy_from_slice(b"long");
+    assert!(vm.call(&mut window).is_err())
+}
+
+#[test]
+fn test_call_errors() {
+    use rt::Vm;
+    let mut vm = BfVm::default();
+    assert_eq!(vm.run(&::bf::Vm::add_one(), &Vec::new()).unwrap_err(), "lambda failed: input exhausted");
+    assert!(vm.run(&::bf::Vm::print(b"1:"), &Vec::new()).unwrap_err().starts_with("broken return value"))
+}
+
+#[test]
+fn test_log_macro() {
+    use rt::Vm;
+    let mut vm = BfVm::default();
+    assert!(match vm.macro_expand("A") { ::rt::MacroResult::Ok(_) => true, _ => false });
+    // printed as is, rather than returned as a string shown with escaped newlines
+    assert!(match vm.macro_expand("log") { ::rt::MacroResult::Continue => true, _ => false });
+    assert_eq!(vm.log_macros.len(), 1)
+}
+
+/// Run the cases of a `.bft` file the way `bft` does, returning the lines of those failing.
+fn failing_cases(src: &str)->Vec<usize> {
+    use rt::{Vm, MacroResult};
+    use bf::testing::{self, Subject, Outcome};
+    let mut vm = BfVm::default();
+    let mut ret = Vec::new();
+    for suite in testing::parse(src).unwrap() {
+        let code = match suite.subject {
+            Subject::Program(code) => code,
+            Subject::Macro(name) => match vm.macro_expand(&name) {
+                MacroResult::Ok(code) => code,
+                _ => panic!("macro `{}` is not a lambda", name)
+            }
+        };
+        for case in suite.cases {
+            if let Outcome::Fail(_) = case.run_hosted(&code, &mut vm) {
+                ret.push(case.line)
+            }
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_builtins_file() {
+    assert_eq!(failing_cases(include_str!("tests/builtins.bft")), Vec::<usize>::new())
+}
+
+#[test]
+fn test_hosted_cases() {
+    let src = "program #host ++%\ncase \"\" -> error \"variable `` not defined\"\n\
+               program #fork Y[+[]]<\ncase \"\" -> error \"illegal pointer movement\"\n";
+    assert_eq!(failing_cases(src), Vec::<usize>::new())
+}
@@ -1,79 +1,273 @@
 use ::rt::Val as RtVal;
 use ::rt::Vm;
-use ::bencode::{object, Value, byte_string};
+use ::bencode::{parse, Value};
 use ::std::fmt::Display;
 use std::rc::Rc;
 
-pub fn pretty(s: &[u8])->String {
-    use std::char::from_u32;
-    let mut fmt = String::new();
-    for &i in s {
-        match from_u32(i as u32) {
-            Some(x) => fmt.push(x),
-            None => fmt.push_str(&format!("0x{:02X}", i))
-        }
+pub use ::rt::pretty;
+
+// kinds of the single entry dictionaries values are tagged with
+const TAGS: [&'static str; 18] = [
+    "str", "macro", "fetch", "lambda", "quote", "eval", "quasi", "unquote",
+    "if", "store", "or", "and", "catch", "func", "call", "dict", "bytes", "error"
+];
+
+fn is_tagged(v: &[(Vec<u8>, Value)])->bool {
+    match v {
+        &[ (ref kind, Value::ByteString(_)) ] => TAGS.iter().any(|x| x.as_bytes() == &kind[..]),
+        _ => false
     }
-    fmt
 }
 
 pub fn rt2bencode<T>(v: RtVal<T>)->Vec<u8>
+    where T: Vm,
+          Vec<u8>: From<T::ByteCode>,
+          RtVal<T>: From<Rc<RtVal<T>>> {
+    encode(rt2value(v))
+}
+
+pub fn rt2value<T>(v: RtVal<T>)->Value
     where T: Vm,
           Vec<u8>: From<T::ByteCode>,
           RtVal<T>: From<Rc<RtVal<T>>> {
     match v {
-        RtVal::Str(s) => byte_string(s.as_bytes()),
+        RtVal::Str(s) => Value::ByteString(s.into_bytes()),
+        // bytes that are valid UTF-8 would read back as a string
+        RtVal::Bytes(s) => match ::std::str::from_utf8(&s) {
+            Ok(_) => object("bytes", s),
+            _ => Value::ByteString(s)
+        },
+        RtVal::Int(n) => Value::Integer(n),
+        RtVal::List(items) => Value::List(items.into_iter().map(rt2value).collect()),
+        RtVal::Dict(mut items) => {
+            items.sort_by(|a, b| a.0.cmp(&b.0));
+            let ret = items.into_iter().map(|(k, v)| (k.into_bytes(), rt2value(v))).collect::<Vec<_>>();
+            // a single string entry could read back as a tagged value
+            if is_tagged(&ret) {
+                object("dict", encode(Value::Dict(ret)))
+            } else {
+                Value::Dict(ret)
+            }
+        },
         RtVal::If(p, t, f) => {
-            let mut ret = vec![ b'l' ];
-            ret.extend(rt2bencode(RtVal::from(p)));
-            ret.extend(rt2bencode(RtVal::from(t)));
-            ret.extend(rt2bencode(RtVal::from(f)));
-            ret.push(b'e');
-            object("if", &ret)
+            object("if", list(vec![ RtVal::from(p), RtVal::from(t), RtVal::from(f) ]))
         },
-        RtVal::Lambda(code) => <Vec<_>>::from(code),
+        RtVal::Lambda(code) => object("lambda", Vec::from(code)),
         RtVal::Call(fst, args) => {
-            let mut ret = vec![ b'l' ];
-            ret.extend(rt2bencode(RtVal::from(fst)));
-            for i in args {
-                ret.extend(rt2bencode(i));
-            }
-            ret.push(b'e');
-            object("call", &ret)
+            let mut items = vec![ RtVal::from(fst) ];
+            items.extend(args);
+            object("call", list(items))
+        },
+        RtVal::Macro(s) => object("macro", s.into_bytes()),
+        RtVal::Quote(v) => object("quote", rt2bencode(RtVal::from(v))),
+        RtVal::Quasi(v) => object("quasi", rt2bencode(RtVal::from(v))),
+        RtVal::Unquote(v) => object("unquote", rt2bencode(RtVal::from(v))),
+        RtVal::Eval(v) => object("eval", rt2bencode(RtVal::from(v))),
+        RtVal::Store(name, v) => {
+            let pair = Value::List(vec![ Value::ByteString(name.into_bytes()), rt2value(RtVal::from(v)) ]);
+            object("store", encode(pair))
         },
-        RtVal::Macro(s) => object("macro", s.as_bytes()),
-        RtVal::Nil => b"0:".iter().cloned().collect()
+        RtVal::Fetch(name) => object("fetch", name.into_bytes()),
+        RtVal::Or(l, r) => object("or", list(vec![ RtVal::from(l), RtVal::from(r) ])),
+        RtVal::And(l, r) => object("and", list(vec![ RtVal::from(l), RtVal::from(r) ])),
+        RtVal::Catch(v, fallback) => object("catch", list(vec![ RtVal::from(v), RtVal::from(fallback) ])),
+        // captured arguments are not sent along
+        RtVal::Func(params, body) | RtVal::Closure(params, body, _) => {
+            let params = params.iter().map(|x| Value::ByteString(x.clone().into_bytes())).collect();
+            object("func", encode(Value::List(vec![ Value::List(params), rt2value(RtVal::from(body)) ])))
+        },
+        // source positions are not sent along
+        RtVal::At(_, v) => rt2value(RtVal::from(v)),
+        RtVal::Nil => Value::ByteString(Vec::new())
     }
 }
 
-pub fn bencode2rt<T>(v: Value)->RtVal<T>
-    where T: Vm, T::ByteCode: Display {
+// outside the functions bound by `Vec<u8>: From<T::ByteCode>`, which hides this conversion
+fn encode(v: Value)->Vec<u8> {
+    Vec::from(v)
+}
+
+// a value tagged with its kind, the content encoded on its own
+fn object(kind: &str, content: Vec<u8>)->Value {
+    Value::Dict(vec![ (kind.as_bytes().to_vec(), Value::ByteString(content)) ])
+}
+
+fn list<T>(items: Vec<RtVal<T>>)->Vec<u8>
+    where T: Vm,
+          Vec<u8>: From<T::ByteCode>,
+          RtVal<T>: From<Rc<RtVal<T>>> {
+    encode(Value::List(items.into_iter().map(rt2value).collect()))
+}
+
+/// Decode what a lambda returned. A list holds its results: none stands for nil,
+/// a single one for itself, and several make a list. So a lambda returning a list
+/// of its own, even an empty or single item one, writes it as the only result,
+/// like `ll1:aee` for `[a]`. A `d5:error...e` value anywhere in there,
+/// which lambdas return to fail explicitly, is an error with its message.
+pub fn bencode2rt<T>(v: Value)->Result<RtVal<T>, String>
+    where T: Vm,
+          T::ByteCode: Display,
+          T::Convert: From<String>,
+          Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
     match v {
-        Value::Dict(mut v) => {
-            if v.len() != 1 {
-                return RtVal::Nil
+        Value::List(mut l) => match l.len() {
+            0 => Ok(RtVal::Nil),
+            1 => value2rt(l.remove(0)),
+            _ => value2rt(Value::List(l))
+        },
+        v => value2rt(v)
+    }
+}
+
+/// Decode a value as encoded by `rt2bencode`, untagged lists and dictionaries as data,
+/// byte strings that are not UTF-8 as bytes, anything else unknown becoming nil.
+/// Dictionary keys must be UTF-8, and an `error` value fails with its message.
+fn value2rt<T>(v: Value)->Result<RtVal<T>, String>
+    where T: Vm,
+          T::ByteCode: Display,
+          T::Convert: From<String>,
+          Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
+    fn nested<T>(v: &[u8])->Result<Vec<RtVal<T>>, String>
+        where T: Vm,
+              T::ByteCode: Display,
+              T::Convert: From<String>,
+              Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
+        match parse(&mut v.iter().cloned()) {
+            Ok(Value::List(l)) => l.into_iter().map(value2rt).collect(),
+            _ => Ok(Vec::new())
+        }
+    }
+    fn dict2rt<T>(v: Vec<(Vec<u8>, Value)>)->Result<RtVal<T>, String>
+        where T: Vm,
+              T::ByteCode: Display,
+              T::Convert: From<String>,
+              Result<T::ByteCode, T::CompileFail>: From<T::Convert> {
+        let mut items = Vec::new();
+        for (k, v) in v {
+            match String::from_utf8(k) {
+                Ok(k) => items.push((k, try!(value2rt(v)))),
+                Err(err) => return Err(format!("dictionary key \"{}\" is not UTF-8", pretty(err.as_bytes())))
             }
-            let (kind, v) = v.pop().unwrap();
+        }
+        Ok(RtVal::Dict(items))
+    }
+    Ok(match v {
+        Value::Dict(v) => if !is_tagged(&v) {
+            try!(dict2rt(v))
+        } else {
+            let (kind, v) = v.into_iter().next().unwrap();
             let kind = match ::std::str::from_utf8(&kind) {
                 Ok(x) => x,
-                _ => return RtVal::Nil
+                _ => return Ok(RtVal::Nil)
             };
-            match (kind, v) {
-                ("str", Value::ByteString(v)) => match String::from_utf8(v) {
+            let v = match v {
+                Value::ByteString(v) => v,
+                _ => return Ok(RtVal::Nil)
+            };
+            match kind {
+                "str" => match String::from_utf8(v) {
                     Ok(x) => RtVal::Str(x),
                     _ => RtVal::Nil
                 },
+                "macro" => match String::from_utf8(v) {
+                    Ok(x) => RtVal::Macro(x),
+                    _ => RtVal::Nil
+                },
+                "fetch" => match String::from_utf8(v) {
+                    Ok(x) => RtVal::Fetch(x),
+                    _ => RtVal::Nil
+                },
+                "lambda" => match String::from_utf8(v) {
+                    Ok(x) => match <Result<_, _>>::from(T::Convert::from(x)) {
+                        Ok(code) => RtVal::Lambda(code),
+                        _ => RtVal::Nil
+                    },
+                    _ => RtVal::Nil
+                },
+                "quote" => match parse(&mut v.iter().cloned()) {
+                    Ok(x) => RtVal::Quote(Rc::new(try!(value2rt(x)))),
+                    _ => RtVal::Nil
+                },
+                "eval" => match parse(&mut v.iter().cloned()) {
+                    Ok(x) => RtVal::Eval(Rc::new(try!(value2rt(x)))),
+                    _ => RtVal::Nil
+                },
+                "quasi" => match parse(&mut v.iter().cloned()) {
+                    Ok(x) => RtVal::Quasi(Rc::new(try!(value2rt(x)))),
+                    _ => RtVal::Nil
+                },
+                "unquote" => match parse(&mut v.iter().cloned()) {
+                    Ok(x) => RtVal::Unquote(Rc::new(try!(value2rt(x)))),
+                    _ => RtVal::Nil
+                },
+                "if" => {
+                    let mut l = try!(nested(&v)).into_iter();
+                    match (l.next(), l.next(), l.next(), l.next()) {
+                        (Some(p), Some(t), Some(f), None) => RtVal::If(Rc::new(p), Rc::new(t), Rc::new(f)),
+                        _ => RtVal::Nil
+                    }
+                },
+                "store" => match parse(&mut v.iter().cloned()) {
+                    Ok(Value::List(ref l)) if l.len() == 2 => match (&l[0], &l[1]) {
+                        (&Value::ByteString(ref name), v) => match String::from_utf8(name.clone()) {
+                            Ok(name) => RtVal::Store(name, Rc::new(try!(value2rt(v.clone())))),
+                            _ => RtVal::Nil
+                        },
+                        _ => RtVal::Nil
+                    },
+                    _ => RtVal::Nil
+                },
+                "or" | "and" | "catch" => {
+                    let mut l = try!(nested(&v)).into_iter();
+                    match (l.next(), l.next(), l.next()) {
+                        (Some(l), Some(r), None) => match kind {
+                            "or" => RtVal::Or(Rc::new(l), Rc::new(r)),
+                            "and" => RtVal::And(Rc::new(l), Rc::new(r)),
+                            _ => RtVal::Catch(Rc::new(l), Rc::new(r))
+                        },
+                        _ => RtVal::Nil
+                    }
+                },
+                "func" => match parse(&mut v.iter().cloned()) {
+                    Ok(Value::List(mut l)) => match (l.pop(), l.pop(), l.pop()) {
+                        (Some(body), Some(Value::List(params)), None) => {
+                            let mut names = Vec::new();
+                            for i in params {
+                                match i {
+                                    Value::ByteString(x) => match String::from_utf8(x) {
+                                        Ok(x) => names.push(x),
+                                        _ => return Ok(RtVal::Nil)
+                                    },
+                                    _ => return Ok(RtVal::Nil)
+                                }
+                            }
+                            RtVal::Func(Rc::new(names), Rc::new(try!(value2rt(body))))
+                        },
+                        _ => RtVal::Nil
+                    },
+                    _ => RtVal::Nil
+                },
+                "call" => {
+                    let mut l = try!(nested(&v)).into_iter();
+                    match l.next() {
+                        Some(first) => RtVal::Call(Rc::new(first), l.collect()),
+                        None => RtVal::Nil
+                    }
+                },
+                "bytes" => RtVal::Bytes(v),
+                "error" => return Err(String::from_utf8_lossy(&v).into_owned()),
+                "dict" => match parse(&mut v.iter().cloned()) {
+                    Ok(Value::Dict(x)) => try!(dict2rt(x)),
+                    _ => RtVal::Nil
+                },
                 _ => RtVal::Nil
             }
         },
-        Value::List(mut l) => if l.is_empty() {
-            RtVal::Nil
-        } else {
-            bencode2rt(l.remove(0))
-        },
+        Value::List(l) => RtVal::List(try!(l.into_iter().map(value2rt).collect())),
         Value::ByteString(s) => match String::from_utf8(s) {
             Ok(s) => RtVal::Str(s),
-            _ => RtVal::Nil
+            Err(err) => RtVal::Bytes(err.into_bytes())
         },
-        _ => RtVal::Nil
-    }
+        Value::Integer(n) => RtVal::Int(n)
+    })
 }